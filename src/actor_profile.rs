@@ -0,0 +1,226 @@
+use crate::aggregate::{grouped_inserts, sql_string, Aggregator};
+use crate::obfuscation::Obfuscation;
+use crate::time_bucket::TimeBucket;
+use crate::types::Event;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// `actor_profiles` over everything loaded so far, from the tables `ActorProfiles` writes.
+pub const ACTOR_PROFILES_VIEW: &str = "CREATE OR REPLACE VIEW actor_profiles AS \
+    SELECT s.actor_name, s.first_seen, s.last_seen, \
+    (SELECT COUNT(*) FROM actor_repos r WHERE r.actor_name = s.actor_name) AS repos, \
+    (SELECT jsonb_object_agg(t.event_type, t.events) FROM \
+    (SELECT e.event_type, SUM(e.events) AS events FROM actor_day_events e \
+    WHERE e.actor_name = s.actor_name GROUP BY e.event_type) t) AS event_counts, \
+    (SELECT COUNT(DISTINCT e.day) FROM actor_day_events e WHERE e.actor_name = s.actor_name) AS active_days, \
+    (SELECT COUNT(*) FROM actor_orgs o WHERE o.actor_name = s.actor_name) AS orgs \
+    FROM actor_seen s;";
+
+/// What one actor did in the events seen since the last drain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActorProfile {
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub repos: BTreeSet<i64>,
+    /// Events per day and event type.
+    pub day_events: BTreeMap<(DateTime<Utc>, String), u64>,
+    /// Org IDs of the repos they had events on.
+    pub orgs: BTreeSet<i64>,
+}
+
+/// Per actor activity, keyed by the same obfuscated identity as the committer output.
+///
+/// Every part of a profile is written so it merges with what earlier flushes and runs wrote:
+/// first and last seen with `LEAST` and `GREATEST`, repos, orgs and active days as rows that are
+/// only inserted once, and event counts per day added up. `ACTOR_PROFILES_VIEW`, written with
+/// the rows, puts them back together.
+pub struct ActorProfiles<'a> {
+    obfuscation: &'a Obfuscation,
+    profiles: BTreeMap<String, ActorProfile>,
+}
+
+impl<'a> ActorProfiles<'a> {
+    pub fn new(obfuscation: &'a Obfuscation) -> ActorProfiles<'a> {
+        ActorProfiles {
+            obfuscation,
+            profiles: BTreeMap::new(),
+        }
+    }
+
+    pub fn profile(&self, login: &str) -> Option<&ActorProfile> {
+        self.profiles.get(login)
+    }
+}
+
+impl<'a> Aggregator for ActorProfiles<'a> {
+    fn observe(&mut self, event: &Event) {
+        let login = match event.actor.login {
+            Some(ref login) if !login.is_empty() => login,
+            _ => return,
+        };
+        let profile = self
+            .profiles
+            .entry(login.clone())
+            .or_insert_with(|| ActorProfile {
+                first_seen: event.created_at,
+                last_seen: event.created_at,
+                repos: BTreeSet::new(),
+                day_events: BTreeMap::new(),
+                orgs: BTreeSet::new(),
+            });
+        if event.created_at < profile.first_seen {
+            profile.first_seen = event.created_at;
+        }
+        if event.created_at > profile.last_seen {
+            profile.last_seen = event.created_at;
+        }
+        if event.repo.id != -1 {
+            profile.repos.insert(event.repo.id);
+        }
+        *profile
+            .day_events
+            .entry((
+                TimeBucket::Day.start(event.created_at),
+                event.event_type.clone(),
+            ))
+            .or_insert(0) += 1;
+        if let Some(ref org) = event.org {
+            if org.id != -1 {
+                profile.orgs.insert(org.id);
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.profiles.len()
+    }
+
+    fn drain(&mut self) -> String {
+        let mut seen = Vec::new();
+        let mut repos = Vec::new();
+        let mut day_events = Vec::new();
+        let mut orgs = Vec::new();
+        for (login, profile) in &self.profiles {
+            let actor_name = sql_string(&Some(self.obfuscation.actor_identity(login)));
+            seen.push(format!(
+                "({}, '{}', '{}')",
+                actor_name, profile.first_seen, profile.last_seen
+            ));
+            for repo_id in &profile.repos {
+                repos.push(format!("({}, {})", actor_name, repo_id));
+            }
+            for ((day, event_type), events) in &profile.day_events {
+                day_events.push(format!(
+                    "({}, '{}', {}, {})",
+                    actor_name,
+                    day,
+                    sql_string(&Some(event_type.clone())),
+                    events
+                ));
+            }
+            for org_id in &profile.orgs {
+                orgs.push(format!("({}, {})", actor_name, org_id));
+            }
+        }
+        self.profiles.clear();
+
+        let mut statements = grouped_inserts(
+            "actor_seen (actor_name, first_seen, last_seen)",
+            &seen,
+            "ON CONFLICT (actor_name) DO UPDATE SET \
+            first_seen = LEAST(actor_seen.first_seen, EXCLUDED.first_seen), \
+            last_seen = GREATEST(actor_seen.last_seen, EXCLUDED.last_seen)",
+        );
+        statements.extend(grouped_inserts(
+            "actor_repos (actor_name, repo_id)",
+            &repos,
+            "ON CONFLICT DO NOTHING",
+        ));
+        statements.extend(grouped_inserts(
+            "actor_day_events (actor_name, day, event_type, events)",
+            &day_events,
+            "ON CONFLICT (actor_name, day, event_type) DO UPDATE SET \
+            events = actor_day_events.events + EXCLUDED.events",
+        ));
+        statements.extend(grouped_inserts(
+            "actor_orgs (actor_name, org_id)",
+            &orgs,
+            "ON CONFLICT DO NOTHING",
+        ));
+        statements.push(ACTOR_PROFILES_VIEW.to_string());
+        statements.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::actor_profile::ActorProfiles;
+    use crate::aggregate::Aggregator;
+    use crate::obfuscation::Obfuscation;
+    use crate::test_events::json_event;
+    use crate::types::Event;
+
+    fn event(event_type: &str, repo_id: i64, org: &str, created_at: &str) -> Event {
+        json_event(event_type, "someone", repo_id, org, created_at)
+    }
+
+    #[test]
+    fn profile_over_range() {
+        let mut profiles = ActorProfiles::new(&Obfuscation::Plaintext);
+        profiles.observe(&event(
+            "PushEvent",
+            1,
+            r#""org": {"id": 9, "login": "foo"}"#,
+            "2017-05-02T07:00:00Z",
+        ));
+        profiles.observe(&event(
+            "PushEvent",
+            1,
+            r#""org": {"id": 9, "login": "foo"}"#,
+            "2017-05-02T09:00:00Z",
+        ));
+        profiles.observe(&event("WatchEvent", 2, "", "2017-05-01T07:00:00Z"));
+
+        let profile = profiles.profile("someone").unwrap();
+        assert_eq!("2017-05-01 07:00:00 UTC", profile.first_seen.to_string());
+        assert_eq!("2017-05-02 09:00:00 UTC", profile.last_seen.to_string());
+        assert_eq!(2, profile.repos.len());
+        assert_eq!(2, profile.day_events.len());
+        assert_eq!(1, profile.orgs.len());
+
+        let sql = profiles.drain();
+        assert!(sql.contains(
+            "INSERT INTO actor_seen (actor_name, first_seen, last_seen) VALUES \
+            ('someone', '2017-05-01 07:00:00 UTC', '2017-05-02 09:00:00 UTC') ON CONFLICT"
+        ));
+        assert!(sql.contains(
+            "INSERT INTO actor_day_events (actor_name, day, event_type, events) VALUES \
+            ('someone', '2017-05-01 00:00:00 UTC', 'WatchEvent', 1), \
+            ('someone', '2017-05-02 00:00:00 UTC', 'PushEvent', 2) ON CONFLICT"
+        ));
+        assert!(sql.ends_with("FROM actor_seen s;"));
+        assert!(profiles.is_empty());
+    }
+
+    // A second run over later hours only adds to what the first wrote
+    #[test]
+    fn later_runs_merge() {
+        let mut first_run = ActorProfiles::new(&Obfuscation::Plaintext);
+        first_run.observe(&event("PushEvent", 1, "", "2017-05-01T07:00:00Z"));
+        first_run.drain();
+
+        let mut second_run = ActorProfiles::new(&Obfuscation::Plaintext);
+        second_run.observe(&event("PushEvent", 2, "", "2017-06-01T07:00:00Z"));
+        let sql = second_run.drain();
+        assert!(sql.contains(
+            "first_seen = LEAST(actor_seen.first_seen, EXCLUDED.first_seen), \
+            last_seen = GREATEST(actor_seen.last_seen, EXCLUDED.last_seen);"
+        ));
+        assert!(sql.contains(
+            "INSERT INTO actor_repos (actor_name, repo_id) VALUES ('someone', 2) \
+            ON CONFLICT DO NOTHING;"
+        ));
+        assert!(sql.contains("events = actor_day_events.events + EXCLUDED.events;"));
+        assert!(!sql.contains("= EXCLUDED."));
+    }
+}
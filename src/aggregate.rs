@@ -0,0 +1,59 @@
+use crate::types::Event;
+use chrono::{DateTime, Utc};
+
+/// A mode that folds events into a summary and writes it out, as SQL unless it says otherwise.
+///
+/// The receiving thread calls `observe` for every event, and `drain` whenever `len` reaches the
+/// mode's flush size and once more at the end of the run. Modes whose output can only be worked
+/// out once everything has been seen return false from `can_flush_early` and are only drained at
+/// the end.
+pub trait Aggregator {
+    fn observe(&mut self, event: &Event);
+
+    /// How many entries are being held, to decide when to flush.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn can_flush_early(&self) -> bool {
+        true
+    }
+
+    /// Extension of the files `drain`'s output goes in, before they're gzipped.
+    fn file_extension(&self) -> &'static str {
+        "txt"
+    }
+
+    /// Everything collected so far as SQL statements, one per line. Leaves the aggregator empty.
+    fn drain(&mut self) -> String;
+}
+
+/// Rows of values, already formatted, as inserts into `into` (the table and its columns) 20 rows
+/// at a time, each ending with `on_conflict`.
+pub(crate) fn grouped_inserts(into: &str, rows: &[String], on_conflict: &str) -> Vec<String> {
+    rows.chunks(20)
+        .map(|chunk| {
+            format!(
+                "INSERT INTO {} VALUES {} {};",
+                into,
+                chunk.join(", "),
+                on_conflict
+            )
+        })
+        .collect()
+}
+
+/// A quoted SQL string, or NULL.
+pub(crate) fn sql_string(value: &Option<String>) -> String {
+    match value {
+        Some(value) => format!("'{}'", value.replace('\'', "''")),
+        None => "NULL".to_string(),
+    }
+}
+
+/// A quoted SQL timestamp, or NULL.
+pub(crate) fn sql_timestamp(value: Option<DateTime<Utc>>) -> String {
+    sql_string(&value.map(|value| value.to_string()))
+}
@@ -0,0 +1,251 @@
+use crate::types::{Actor, Event};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::str::FromStr;
+
+const SECONDS_PER_HOUR: i64 = 60 * 60;
+const SECONDS_PER_DAY: i64 = 24 * SECONDS_PER_HOUR;
+
+/// How far back from a new hour or day counts are dropped. Wide enough to cover gaps in the
+/// source files, narrow enough to leave alone the other download thread's, which is months away.
+const STALE_BUCKETS: i64 = 24;
+
+/// Counts for `bucket`, an hour or day, starting it if it's new. Events can lag a little behind
+/// the newest, so a new bucket means the one before the one before it is over, and anything
+/// older is dropped along with it.
+fn bucket_counts<T: Default>(
+    buckets: &mut BTreeMap<i64, HashMap<String, T>>,
+    bucket: i64,
+) -> &mut HashMap<String, T> {
+    if !buckets.contains_key(&bucket) {
+        let stale: Vec<i64> = buckets
+            .range(bucket - STALE_BUCKETS..bucket - 1)
+            .map(|(stale, _)| *stale)
+            .collect();
+        for stale in stale {
+            buckets.remove(&stale);
+        }
+    }
+    buckets.entry(bucket).or_default()
+}
+
+/// True for logins that say they're a bot: GitHub Apps like `dependabot[bot]` and accounts
+/// named like `some-ci-bot`.
+pub fn is_bot_login(login: &str) -> bool {
+    let login = login.to_lowercase();
+    login.ends_with("[bot]") || login.ends_with("-bot") || login.ends_with("_bot")
+}
+
+impl Actor {
+    pub fn has_bot_login(&self) -> bool {
+        match self.login {
+            Some(ref login) => is_bot_login(login),
+            None => false,
+        }
+    }
+}
+
+/// What to do with bots in committer output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BotHandling {
+    /// Count them like anyone else.
+    Include,
+    /// Leave them out.
+    Exclude,
+    /// Count them with a column saying they're a bot.
+    Tag,
+}
+
+impl FromStr for BotHandling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BotHandling, String> {
+        match s {
+            "include" => Ok(BotHandling::Include),
+            "exclude" => Ok(BotHandling::Exclude),
+            "tag" => Ok(BotHandling::Tag),
+            other => Err(format!(
+                "Unknown bot handling {}, expected include, exclude or tag",
+                other
+            )),
+        }
+    }
+}
+
+/// Decides if an account is a bot from its login, a list of known bots and how it behaves:
+/// pushing more often than a person would in an hour or committing to more repos in a day.
+///
+/// Behavior is only known once it's been seen, so an account counts as a bot from the event
+/// that crosses a threshold onwards. Only `flagged` at the end of a run is complete.
+/// Counts are kept per hour and day of the events and dropped once those are over.
+#[derive(Debug, Clone)]
+pub struct BotClassifier {
+    known_bots: BTreeSet<String>,
+    max_pushes_per_hour: u32,
+    max_repos_per_day: usize,
+    /// Hour to login to number of pushes.
+    pushes: BTreeMap<i64, HashMap<String, u32>>,
+    /// Day to login to repos committed to.
+    repos: BTreeMap<i64, HashMap<String, HashSet<i64>>>,
+    /// Accounts that crossed a threshold, spelled as in the events so they match committer output.
+    flagged: BTreeSet<String>,
+    /// `flagged` lowercased, for comparing.
+    flagged_lowercase: HashSet<String>,
+}
+
+impl BotClassifier {
+    pub fn new(
+        known_bots: BTreeSet<String>,
+        max_pushes_per_hour: u32,
+        max_repos_per_day: usize,
+    ) -> BotClassifier {
+        BotClassifier {
+            known_bots: known_bots
+                .iter()
+                .map(|login| login.to_lowercase())
+                .collect(),
+            max_pushes_per_hour,
+            max_repos_per_day,
+            pushes: BTreeMap::new(),
+            repos: BTreeMap::new(),
+            flagged: BTreeSet::new(),
+            flagged_lowercase: HashSet::new(),
+        }
+    }
+
+    /// Keep track of how often the committer of this event pushes and how many repos they touch.
+    pub fn observe(&mut self, event: &Event) {
+        if !event.is_commit_event() {
+            return;
+        }
+        let actor = event.as_commit_event().actor;
+        let login = actor.to_lowercase();
+        if login.is_empty() || self.flagged_lowercase.contains(&login) {
+            return;
+        }
+        let timestamp = event.created_at.timestamp();
+
+        if event.is_direct_push_event() {
+            let hour = timestamp.div_euclid(SECONDS_PER_HOUR);
+            let pushes = bucket_counts(&mut self.pushes, hour)
+                .entry(login.clone())
+                .or_insert(0);
+            *pushes += 1;
+            if *pushes > self.max_pushes_per_hour {
+                info!("Flagging {} as a bot, {} pushes in an hour", actor, pushes);
+                self.flag(actor, login);
+                return;
+            }
+        }
+
+        let day = timestamp.div_euclid(SECONDS_PER_DAY);
+        let repos = bucket_counts(&mut self.repos, day)
+            .entry(login.clone())
+            .or_default();
+        repos.insert(event.repo.id);
+        if repos.len() > self.max_repos_per_day {
+            info!(
+                "Flagging {} as a bot, {} repos in a day",
+                actor,
+                repos.len()
+            );
+            self.flag(actor, login);
+        }
+    }
+
+    fn flag(&mut self, actor: String, login: String) {
+        self.flagged.insert(actor);
+        self.flagged_lowercase.insert(login);
+    }
+
+    pub fn is_bot(&self, login: &str) -> bool {
+        let login = login.to_lowercase();
+        is_bot_login(&login)
+            || self.known_bots.contains(&login)
+            || self.flagged_lowercase.contains(&login)
+    }
+
+    /// Accounts flagged by their behavior rather than their login or the list.
+    pub fn flagged(&self) -> &BTreeSet<String> {
+        &self.flagged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bots::{is_bot_login, BotClassifier};
+    use crate::test_events::json_event;
+    use crate::types::Event;
+    use std::collections::BTreeSet;
+
+    fn push_to(repo_id: i64, login: &str) -> Event {
+        push_at(repo_id, login, "2017-05-01T07:00:00Z")
+    }
+
+    fn push_at(repo_id: i64, login: &str, created_at: &str) -> Event {
+        json_event(
+            "PushEvent",
+            login,
+            repo_id,
+            r#""payload": {"size": 1}"#,
+            created_at,
+        )
+    }
+
+    #[test]
+    fn bots_by_login_list_and_behavior() {
+        assert!(is_bot_login("dependabot[bot]"));
+        assert!(is_bot_login("Some-CI-Bot"));
+        assert!(!is_bot_login("abbot"));
+        assert!(push_to(1, "renovate[bot]").actor.has_bot_login());
+
+        let mut known_bots = BTreeSet::new();
+        known_bots.insert("Travis".to_string());
+        let mut classifier = BotClassifier::new(known_bots, 2, 3);
+        assert!(classifier.is_bot("travis"));
+        assert!(!classifier.is_bot("busy-human"));
+
+        // three pushes in an hour is over the limit of two
+        for _ in 0..3 {
+            classifier.observe(&push_to(1, "Busy-Human"));
+        }
+        assert!(classifier.is_bot("busy-human"));
+        // kept as spelled so it matches the committer output
+        assert!(classifier.flagged().contains("Busy-Human"));
+
+        // four repos in a day is over the limit of three, but not four repos over four days
+        for day in 1..5 {
+            classifier.observe(&push_at(
+                day * 10,
+                "spreads-out",
+                &format!("2017-05-0{}T07:00:00Z", day),
+            ));
+        }
+        assert!(!classifier.is_bot("spreads-out"));
+        for repo_id in 1..5 {
+            classifier.observe(&push_to(repo_id * 10, "spreads-out"));
+        }
+        assert!(classifier.is_bot("spreads-out"));
+        assert_eq!(2, classifier.flagged().len());
+    }
+
+    // Counts go once their hour or day is over, but not while events for it may still come in
+    #[test]
+    fn counts_dropped_after_their_hour_and_day() {
+        let mut classifier = BotClassifier::new(BTreeSet::new(), 2, 3);
+        classifier.observe(&push_at(1, "someone", "2017-05-01T07:00:00Z"));
+        classifier.observe(&push_at(1, "someone", "2017-05-01T08:00:00Z"));
+        // late, from a file fetched alongside the next hour's
+        classifier.observe(&push_at(1, "someone", "2017-05-01T07:30:00Z"));
+        assert_eq!(2, classifier.pushes.len());
+        classifier.observe(&push_at(1, "someone", "2017-05-01T09:00:00Z"));
+        assert_eq!(2, classifier.pushes.len());
+        // the other download thread, months later, leaves these alone
+        classifier.observe(&push_at(1, "other", "2017-09-01T07:00:00Z"));
+        assert_eq!(3, classifier.pushes.len());
+
+        classifier.observe(&push_at(1, "someone", "2017-05-02T07:00:00Z"));
+        classifier.observe(&push_at(1, "someone", "2017-05-03T07:00:00Z"));
+        assert_eq!(3, classifier.repos.len());
+        assert!(!classifier.is_bot("someone"));
+    }
+}
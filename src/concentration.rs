@@ -0,0 +1,272 @@
+use crate::aggregate::Aggregator;
+use crate::time_bucket::TimeBucket;
+use crate::types::Event;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::BTreeMap;
+
+/// How concentrated a repo's commits are among its committers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Concentration {
+    pub contributors: usize,
+    pub commits: i64,
+    /// Fewest committers covering at least half the commits.
+    pub bus_factor: usize,
+    /// 0 when everyone committed the same amount, towards 1 when one committer did it all.
+    pub gini: f64,
+    /// Fraction of commits by the top committer.
+    pub top_share: f64,
+}
+
+impl Concentration {
+    /// From each committer's commit count. None if there are no commits.
+    pub fn from_counts(counts: &[i64]) -> Option<Concentration> {
+        let mut counts: Vec<i64> = counts.iter().cloned().filter(|c| *c > 0).collect();
+        let commits: i64 = counts.iter().sum();
+        if commits == 0 {
+            return None;
+        }
+        counts.sort_unstable();
+
+        let mut covered = 0;
+        let mut bus_factor = 0;
+        for count in counts.iter().rev() {
+            covered += count;
+            bus_factor += 1;
+            if covered * 2 >= commits {
+                break;
+            }
+        }
+
+        let n = counts.len() as f64;
+        let weighted: f64 = counts
+            .iter()
+            .enumerate()
+            .map(|(i, count)| (i as f64 + 1.0) * *count as f64)
+            .sum();
+        let gini = (2.0 * weighted) / (n * commits as f64) - (n + 1.0) / n;
+
+        Some(Concentration {
+            contributors: counts.len(),
+            commits,
+            bus_factor,
+            gini,
+            top_share: counts[counts.len() - 1] as f64 / commits as f64,
+        })
+    }
+}
+
+/// Commit concentration per repo over the days up to the newest event in the run.
+///
+/// The window depends on the last event, so nothing's written until the end. Days that have
+/// fallen out of the window are dropped as newer events come in. If the run starts after the
+/// window would, the rows say so by starting at the run's first event instead.
+#[derive(Debug, Clone)]
+pub struct ContributorConcentration {
+    window: Duration,
+    /// Commits per committer per day, per repo.
+    commits: BTreeMap<i64, BTreeMap<String, BTreeMap<DateTime<Utc>, i64>>>,
+    newest: Option<DateTime<Utc>>,
+    /// Earliest event of any type, so we know where the run's coverage starts.
+    oldest: Option<DateTime<Utc>>,
+}
+
+impl ContributorConcentration {
+    pub fn new(window_days: i64) -> ContributorConcentration {
+        ContributorConcentration {
+            window: Duration::days(window_days),
+            commits: BTreeMap::new(),
+            newest: None,
+            oldest: None,
+        }
+    }
+
+    /// Start of the first day counted. Commits are counted per day, so this is at midnight.
+    fn counted_from(&self) -> Option<DateTime<Utc>> {
+        self.newest
+            .map(|newest| TimeBucket::Day.start(newest - self.window))
+    }
+
+    /// Start and end of the span the counts cover, if we've seen any commits. That's the trailing
+    /// window, unless the run's first event came later.
+    pub fn window(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        match (self.counted_from(), self.newest, self.oldest) {
+            (Some(counted_from), Some(newest), Some(oldest)) if oldest > counted_from => {
+                Some((oldest, newest))
+            }
+            (Some(counted_from), Some(newest), _) => Some((counted_from, newest)),
+            _ => None,
+        }
+    }
+
+    /// Forget commits from before the window.
+    fn prune(&mut self, window_start: DateTime<Utc>) {
+        for committers in self.commits.values_mut() {
+            for days in committers.values_mut() {
+                *days = days.split_off(&window_start);
+            }
+            committers.retain(|_, days| !days.is_empty());
+        }
+        self.commits.retain(|_, committers| !committers.is_empty());
+    }
+
+    pub fn concentration(&self) -> BTreeMap<i64, Concentration> {
+        let window_start = match self.counted_from() {
+            Some(window_start) => window_start,
+            None => return BTreeMap::new(),
+        };
+        self.commits
+            .iter()
+            .filter_map(|(repo_id, committers)| {
+                let counts: Vec<i64> = committers
+                    .values()
+                    .map(|days| days.range(window_start..).map(|(_, commits)| commits).sum())
+                    .collect();
+                Concentration::from_counts(&counts).map(|c| (*repo_id, c))
+            })
+            .collect()
+    }
+}
+
+impl Aggregator for ContributorConcentration {
+    fn observe(&mut self, event: &Event) {
+        if self.oldest.is_none() || self.oldest > Some(event.created_at) {
+            self.oldest = Some(event.created_at);
+        }
+        if !event.is_commit_event() {
+            return;
+        }
+        let commit_event = event.as_commit_event();
+        if commit_event.actor.is_empty() || commit_event.repo_id == -1 {
+            return;
+        }
+        let old_start = self.counted_from();
+        if self.newest < Some(event.created_at) {
+            self.newest = Some(event.created_at);
+        }
+        let window_start = self.counted_from().expect("we've just seen a commit");
+        if window_start > TimeBucket::Day.start(event.created_at) {
+            return;
+        }
+        match old_start {
+            Some(old_start) if old_start < window_start => self.prune(window_start),
+            _ => {}
+        }
+        *self
+            .commits
+            .entry(commit_event.repo_id)
+            .or_default()
+            .entry(commit_event.actor)
+            .or_default()
+            .entry(TimeBucket::Day.start(event.created_at))
+            .or_insert(0) += event.commit_count();
+    }
+
+    fn len(&self) -> usize {
+        self.commits.len()
+    }
+
+    fn can_flush_early(&self) -> bool {
+        false
+    }
+
+    fn drain(&mut self) -> String {
+        let (window_start, window_end) = match self.window() {
+            Some(window) => window,
+            None => return String::new(),
+        };
+        let rows: Vec<String> = self
+            .concentration()
+            .iter()
+            .map(|(repo_id, c)| {
+                format!(
+                    "({}, '{}', '{}', {}, {}, {}, {:.4}, {:.4})",
+                    repo_id,
+                    window_start,
+                    window_end,
+                    c.contributors,
+                    c.commits,
+                    c.bus_factor,
+                    c.gini,
+                    c.top_share
+                )
+            })
+            .collect();
+        self.commits.clear();
+        self.newest = None;
+        self.oldest = None;
+        rows.chunks(20)
+            .map(|chunk| {
+                format!("INSERT INTO contributor_concentration (repo_id, window_start, window_end, contributors, commits, bus_factor, gini, top_share) VALUES {} \
+                    ON CONFLICT (repo_id, window_end) DO UPDATE SET \
+                    window_start = EXCLUDED.window_start, \
+                    contributors = EXCLUDED.contributors, \
+                    commits = EXCLUDED.commits, \
+                    bus_factor = EXCLUDED.bus_factor, \
+                    gini = EXCLUDED.gini, \
+                    top_share = EXCLUDED.top_share;", chunk.join(", "))
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregator;
+    use crate::concentration::{Concentration, ContributorConcentration};
+    use crate::test_events::{event_for, may, push_by};
+
+    #[test]
+    fn concentration_metrics() {
+        let even = Concentration::from_counts(&[5, 5, 5, 5]).unwrap();
+        assert_eq!(2, even.bus_factor);
+        assert!(even.gini.abs() < 1e-9);
+        assert!((even.top_share - 0.25).abs() < 1e-9);
+
+        let skewed = Concentration::from_counts(&[1, 0, 1, 8]).unwrap();
+        assert_eq!(3, skewed.contributors);
+        assert_eq!(1, skewed.bus_factor);
+        assert!((skewed.top_share - 0.8).abs() < 1e-9);
+        // (2 * (1 + 2 + 24)) / (3 * 10) - 4 / 3
+        assert!((skewed.gini - 0.4666).abs() < 1e-3);
+
+        assert_eq!(None, Concentration::from_counts(&[0]));
+    }
+
+    #[test]
+    fn only_the_trailing_window_counts() {
+        let mut concentration = ContributorConcentration::new(7);
+        concentration.observe(&push_by("old", 155, 100, may(1, 7)));
+        concentration.observe(&push_by("alice", 155, 3, may(22, 7)));
+        concentration.observe(&push_by("carol", 155, 2, may(20, 7)));
+        concentration.observe(&push_by("bob", 155, 1, may(28, 7)));
+        // too old by the time it turns up
+        concentration.observe(&push_by("dave", 155, 9, may(2, 7)));
+        assert_eq!(1, concentration.len());
+        assert_eq!(2, concentration.commits[&155].len());
+
+        let repo = concentration.concentration()[&155];
+        assert_eq!(
+            (2, 4, 1),
+            (repo.contributors, repo.commits, repo.bus_factor)
+        );
+
+        let sql = concentration.drain();
+        assert!(sql.contains(
+            "(155, '2017-05-21 00:00:00 UTC', '2017-05-28 07:00:00 UTC', 2, 4, 1, 0.2500, 0.7500)"
+        ));
+    }
+
+    #[test]
+    fn short_runs_say_what_they_cover() {
+        let mut concentration = ContributorConcentration::new(7);
+        concentration.observe(&event_for("WatchEvent", 155, may(27, 3)));
+        concentration.observe(&push_by("alice", 155, 2, may(27, 7)));
+        concentration.observe(&push_by("bob", 155, 1, may(28, 7)));
+
+        let sql = concentration.drain();
+        assert!(sql.contains(
+            "(155, '2017-05-27 03:00:00 UTC', '2017-05-28 07:00:00 UTC', 2, 3, 1, 0.1667, 0.6667)"
+        ));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::aggregate::{grouped_inserts, sql_string, Aggregator};
+use crate::obfuscation::Obfuscation;
+use crate::time_bucket::TimeBucket;
+use crate::types::Event;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Distinct, new and returning committers per repo per month over everything loaded so far, from
+/// the tables `MonthlyContributors` writes.
+pub const MONTHLY_CONTRIBUTORS_VIEW: &str = "CREATE OR REPLACE VIEW monthly_contributors AS \
+    SELECT m.repo_id, m.month, COUNT(*) AS contributors, \
+    COUNT(*) FILTER (WHERE m.month = f.first_month) AS new_contributors, \
+    COUNT(*) FILTER (WHERE m.month > f.first_month) AS returning_contributors \
+    FROM contributor_months m \
+    JOIN contributor_first_months f USING (repo_id, actor_name) \
+    GROUP BY m.repo_id, m.month;";
+
+/// The months each committer committed to each repo, counted the same way as the committer count
+/// mode, so distinct, new and returning committers per repo per month can be worked out in SQL
+/// over every run.
+///
+/// `contributor_months` gets a row for each repo, committer and month they committed in, and
+/// `contributor_first_months` the earliest month each committer committed to each repo, kept
+/// across runs with `LEAST`. Neither depends on what else is in the run, so it's flushed like
+/// any other mode and runs can come in any order. `MONTHLY_CONTRIBUTORS_VIEW`, written with the
+/// rows, counts them up.
+pub struct MonthlyContributors<'a> {
+    obfuscation: &'a Obfuscation,
+    months_active: BTreeMap<(i64, String), BTreeSet<DateTime<Utc>>>,
+}
+
+impl<'a> MonthlyContributors<'a> {
+    pub fn new(obfuscation: &'a Obfuscation) -> MonthlyContributors<'a> {
+        MonthlyContributors {
+            obfuscation,
+            months_active: BTreeMap::new(),
+        }
+    }
+}
+
+impl<'a> Aggregator for MonthlyContributors<'a> {
+    fn observe(&mut self, event: &Event) {
+        if !event.is_commit_event() {
+            return;
+        }
+        let commit_event = event.as_commit_event();
+        if commit_event.actor.is_empty() || commit_event.repo_id == -1 {
+            return;
+        }
+        self.months_active
+            .entry((commit_event.repo_id, commit_event.actor))
+            .or_default()
+            .insert(TimeBucket::Month.start(event.created_at));
+    }
+
+    fn len(&self) -> usize {
+        self.months_active.len()
+    }
+
+    fn drain(&mut self) -> String {
+        let mut month_rows: Vec<String> = Vec::new();
+        let mut first_month_rows: Vec<String> = Vec::new();
+        for ((repo_id, actor), months) in &self.months_active {
+            let actor_name = sql_string(&Some(self.obfuscation.actor_identity(actor)));
+            for month in months {
+                month_rows.push(format!("({}, {}, '{}')", repo_id, actor_name, month));
+            }
+            if let Some(first_month) = months.iter().next() {
+                first_month_rows.push(format!("({}, {}, '{}')", repo_id, actor_name, first_month));
+            }
+        }
+        self.months_active.clear();
+        let mut statements = grouped_inserts(
+            "contributor_months (repo_id, actor_name, month)",
+            &month_rows,
+            "ON CONFLICT DO NOTHING",
+        );
+        statements.extend(grouped_inserts(
+            "contributor_first_months (repo_id, actor_name, first_month)",
+            &first_month_rows,
+            "ON CONFLICT (repo_id, actor_name) DO UPDATE SET \
+            first_month = LEAST(contributor_first_months.first_month, EXCLUDED.first_month)",
+        ));
+        statements.push(MONTHLY_CONTRIBUTORS_VIEW.to_string());
+        statements.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregator;
+    use crate::contributors::{MonthlyContributors, MONTHLY_CONTRIBUTORS_VIEW};
+    use crate::obfuscation::Obfuscation;
+    use crate::test_events::push_by;
+    use crate::types::Event;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn contributor_months_and_first_months() {
+        let obfuscation = Obfuscation::Plaintext;
+        let mut contributors = MonthlyContributors::new(&obfuscation);
+        // out of order, like events from the two download threads
+        contributors.observe(&push_by(
+            "alice",
+            155,
+            1,
+            Utc.ymd(2017, 6, 2).and_hms(7, 0, 0),
+        ));
+        contributors.observe(&push_by(
+            "alice",
+            155,
+            1,
+            Utc.ymd(2017, 5, 1).and_hms(7, 0, 0),
+        ));
+        contributors.observe(&push_by(
+            "alice",
+            155,
+            1,
+            Utc.ymd(2017, 5, 20).and_hms(7, 0, 0),
+        ));
+        contributors.observe(&push_by(
+            "bob",
+            155,
+            1,
+            Utc.ymd(2017, 6, 3).and_hms(7, 0, 0),
+        ));
+        contributors.observe(&Event::new());
+        assert_eq!(2, contributors.len());
+
+        let expected = "INSERT INTO contributor_months (repo_id, actor_name, month) VALUES \
+            (155, 'alice', '2017-05-01 00:00:00 UTC'), (155, 'alice', '2017-06-01 00:00:00 UTC'), \
+            (155, 'bob', '2017-06-01 00:00:00 UTC') ON CONFLICT DO NOTHING;\n\
+            INSERT INTO contributor_first_months (repo_id, actor_name, first_month) VALUES \
+            (155, 'alice', '2017-05-01 00:00:00 UTC'), (155, 'bob', '2017-06-01 00:00:00 UTC') \
+            ON CONFLICT (repo_id, actor_name) DO UPDATE SET \
+            first_month = LEAST(contributor_first_months.first_month, EXCLUDED.first_month);\n";
+        assert_eq!(
+            format!("{}{}", expected, MONTHLY_CONTRIBUTORS_VIEW),
+            contributors.drain()
+        );
+        assert!(contributors.is_empty());
+    }
+}
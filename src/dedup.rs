@@ -0,0 +1,106 @@
+use crate::types::Event;
+use std::collections::{BTreeMap, HashSet};
+
+const SECONDS_PER_HOUR: i64 = 60 * 60;
+
+/// Drops events whose GHA event id we've already seen.
+///
+/// A republished or overlapping hour has the same events with the same `created_at`, so ids are
+/// kept in a set per hour of `created_at`. To bound memory, hours more than `window_hours` older
+/// than the newest hour seen are forgotten: a duplicate of an event from a forgotten hour gets through.
+#[derive(Debug, Clone)]
+pub struct EventDeduper {
+    window_hours: i64,
+    newest_hour: i64,
+    seen: BTreeMap<i64, HashSet<i64>>,
+    pub duplicates: u64,
+}
+
+impl EventDeduper {
+    pub fn new(window_hours: i64) -> EventDeduper {
+        EventDeduper {
+            window_hours,
+            newest_hour: i64::MIN,
+            seen: BTreeMap::new(),
+            duplicates: 0,
+        }
+    }
+
+    /// True the first time we see an event. Events without an id always count as new.
+    pub fn is_new(&mut self, event: &Event) -> bool {
+        if event.id == -1 {
+            return true;
+        }
+        let hour = event.created_at.timestamp().div_euclid(SECONDS_PER_HOUR);
+        if hour > self.newest_hour {
+            self.newest_hour = hour;
+            let oldest_hour_kept = hour - self.window_hours;
+            self.seen = self.seen.split_off(&oldest_hour_kept);
+        }
+        if hour < self.newest_hour - self.window_hours {
+            // We've already forgotten this hour, no way to tell.
+            return true;
+        }
+        if self.seen.entry(hour).or_default().insert(event.id) {
+            true
+        } else {
+            self.duplicates += 1;
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dedup::EventDeduper;
+    use crate::gha_sources::sort_chronologically;
+    use crate::test_events::{event_for, may};
+    use crate::types::Event;
+    use chrono::Duration;
+
+    fn event_at(id: i64, hour: u32) -> Event {
+        let mut event = event_for("PushEvent", 155, may(1, hour) + Duration::minutes(30));
+        event.id = id;
+        event
+    }
+
+    #[test]
+    fn duplicate_ids_dropped_within_window() {
+        let mut deduper = EventDeduper::new(2);
+        assert!(deduper.is_new(&event_at(1, 7)));
+        assert!(deduper.is_new(&event_at(2, 7)));
+        assert!(!deduper.is_new(&event_at(1, 7)));
+        assert!(deduper.is_new(&event_at(3, 9)));
+        // hour 7 is still inside the window
+        assert!(!deduper.is_new(&event_at(2, 7)));
+        assert_eq!(2, deduper.duplicates);
+
+        // hour 7 is forgotten once we're three hours past it
+        assert!(deduper.is_new(&event_at(4, 10)));
+        assert!(deduper.is_new(&event_at(2, 7)));
+        assert_eq!(2, deduper.duplicates);
+    }
+
+    // Every hour republishes the previous hour's last event. Listed the way S3 lists them, hours
+    // 2 to 9 come after 19 and would be outside the window.
+    #[test]
+    fn duplicates_dropped_in_s3_listing_order() {
+        let mut files: Vec<String> = (0..24)
+            .map(|hour| format!("2017-05-01-{}.json.gz", hour))
+            .collect();
+        files.sort();
+        sort_chronologically(&mut files);
+
+        let mut deduper = EventDeduper::new(2);
+        for file in &files {
+            let hour: u32 = file["2017-05-01-".len()..file.len() - ".json.gz".len()]
+                .parse()
+                .unwrap();
+            if hour > 0 {
+                deduper.is_new(&event_at(i64::from(hour) - 1, hour - 1));
+            }
+            assert!(deduper.is_new(&event_at(i64::from(hour), hour)));
+        }
+        assert_eq!(23, deduper.duplicates);
+    }
+}
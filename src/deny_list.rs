@@ -0,0 +1,379 @@
+extern crate serde_json;
+
+use crate::merge::inserts;
+use crate::obfuscation::Obfuscation;
+use crate::types::{Actor, Event, Pre2015Event};
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+/// Columns of our output that hold a repo ID.
+const REPO_ID_COLUMNS: &[&str] = &[
+    "repo_id",
+    "parent_repo_id",
+    "fork_repo_id",
+    "repo_id_a",
+    "repo_id_b",
+];
+
+/// Columns of our output that hold an obfuscated login.
+const ACTOR_COLUMNS: &[&str] = &["actor_name", "author", "merged_by", "fork_owner"];
+
+/// The kinds of output the erasure report can look through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// SQL statements, in `.txt.gz` files.
+    Sql,
+    /// Repo ID edge lists, in `.csv.gz` files.
+    Csv,
+    /// JSON, one object per line or a whole document, in `.json.gz` files.
+    Json,
+}
+
+impl OutputFormat {
+    /// The format of one of our output files, or None for files that aren't datasets: our own
+    /// reports and the quarantined source lines.
+    pub fn of_key(key: &str) -> Option<OutputFormat> {
+        let file_name = key.rsplit('/').next().unwrap_or(key);
+        if file_name.ends_with(".txt.gz") {
+            Some(OutputFormat::Sql)
+        } else if file_name.ends_with(".csv.gz") {
+            Some(OutputFormat::Csv)
+        } else if file_name.ends_with(".json.gz") && !file_name.starts_with("quarantine_") {
+            Some(OutputFormat::Json)
+        } else {
+            None
+        }
+    }
+}
+
+/// Actors and repositories that have asked to be removed from our datasets.
+///
+/// Loaded from JSON such as `{"logins": ["someone"], "actor_ids": [1234], "repo_ids": [5678]}`,
+/// any of the lists can be left out. Logins are compared case-insensitively like GitHub does, but
+/// list them as GitHub spells them: our output has the login as spelled in the events, so that's
+/// what the erasure report looks for.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct DenyList {
+    #[serde(default)]
+    pub logins: BTreeSet<String>,
+    /// `logins` lowercased, for comparing.
+    #[serde(skip)]
+    lowercase_logins: BTreeSet<String>,
+    #[serde(default)]
+    pub actor_ids: BTreeSet<i64>,
+    #[serde(default)]
+    pub repo_ids: BTreeSet<i64>,
+}
+
+impl DenyList {
+    pub fn from_json(json: &str) -> Result<DenyList, String> {
+        let mut deny_list: DenyList =
+            serde_json::from_str(json).map_err(|e| format!("Couldn't parse deny list: {}", e))?;
+        deny_list.lowercase_logins = deny_list
+            .logins
+            .iter()
+            .map(|login| login.to_lowercase())
+            .collect();
+        Ok(deny_list)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.logins.is_empty() && self.actor_ids.is_empty() && self.repo_ids.is_empty()
+    }
+
+    fn has_login(&self, login: &Option<String>) -> bool {
+        match login {
+            Some(login) => self.lowercase_logins.contains(&login.to_lowercase()),
+            None => false,
+        }
+    }
+
+    fn has_actor(&self, actor: Option<&Actor>) -> bool {
+        match actor {
+            Some(actor) => self.actor_ids.contains(&actor.id) || self.has_login(&actor.login),
+            None => false,
+        }
+    }
+
+    /// True if the event involves anyone or anything on the list: its repo and actor, and anyone
+    /// else it carries that we write out, such as the author and merger of a PR, the author of an
+    /// issue and the owner of a fork.
+    pub fn excludes(&self, event: &Event) -> bool {
+        if self.repo_ids.contains(&event.repo.id) || self.has_actor(Some(&event.actor)) {
+            return true;
+        }
+        let payload = match event.payload {
+            Some(ref payload) => payload,
+            None => return false,
+        };
+        if let Some(ref pull_request) = payload.pull_request {
+            if self.has_actor(pull_request.actor.as_ref())
+                || self.has_actor(pull_request.merged_by.as_ref())
+            {
+                return true;
+            }
+        }
+        if let Some(ref issue) = payload.issue {
+            if self.has_actor(issue.user.as_ref()) {
+                return true;
+            }
+        }
+        match payload.forkee {
+            Some(ref forkee) => self.has_actor(forkee.owner.as_ref()),
+            None => false,
+        }
+    }
+
+    /// Pre-2015 events only have the actor's login.
+    pub fn excludes_old(&self, event: &Pre2015Event) -> bool {
+        self.repo_ids.contains(&event.repo_id()) || self.has_login(&Some(event.actor_name()))
+    }
+
+    /// Everything on the list in one of our output files, as `column value` with the table in
+    /// front for SQL. Only columns that hold a repo ID or an obfuscated login are looked at, by
+    /// name: the column list of each SQL insert, the header row of CSV and the keys of JSON
+    /// objects. Logins are looked for as listed and lowercased. Actor IDs aren't written to the
+    /// output, list the login as well to find them.
+    pub fn find_in_output(
+        &self,
+        obfuscation: &Obfuscation,
+        format: OutputFormat,
+        contents: &str,
+    ) -> Result<Vec<String>, String> {
+        let identities: BTreeSet<String> = self
+            .logins
+            .iter()
+            .chain(&self.lowercase_logins)
+            .map(|login| obfuscation.actor_identity(login))
+            .collect();
+        let mut found: BTreeSet<String> = BTreeSet::new();
+        let mut check = |label: String, column: &str, value: &str| {
+            let denied = if REPO_ID_COLUMNS.contains(&column) {
+                match value.parse::<i64>() {
+                    Ok(repo_id) => self.repo_ids.contains(&repo_id),
+                    Err(_) => false,
+                }
+            } else {
+                ACTOR_COLUMNS.contains(&column) && identities.contains(value)
+            };
+            if denied {
+                found.insert(format!("{} {}", label, value));
+            }
+        };
+
+        match format {
+            OutputFormat::Sql => {
+                for insert in inserts(contents)? {
+                    for row in &insert.rows {
+                        for (column, value) in insert.columns.iter().zip(row) {
+                            check(format!("{}.{}", insert.table, column), column, value);
+                        }
+                    }
+                }
+            }
+            OutputFormat::Csv => {
+                let mut lines = contents.lines();
+                let columns: Vec<&str> = lines.next().unwrap_or_default().split(',').collect();
+                for line in lines {
+                    for (column, value) in columns.iter().zip(line.split(',')) {
+                        check(column.to_string(), column, value);
+                    }
+                }
+            }
+            OutputFormat::Json => {
+                let documents: Vec<Value> = match serde_json::from_str(contents) {
+                    Ok(document) => vec![document],
+                    Err(_) => contents
+                        .lines()
+                        .filter(|line| !line.trim().is_empty())
+                        .map(serde_json::from_str)
+                        .collect::<Result<Vec<Value>, _>>()
+                        .map_err(|e| format!("Couldn't parse JSON output: {}", e))?,
+                };
+                for document in &documents {
+                    json_fields(document, &mut |column, value| {
+                        check(column.to_string(), column, value)
+                    });
+                }
+            }
+        }
+        Ok(found.into_iter().collect())
+    }
+}
+
+/// Every string or number in a JSON document with the key it's under, however deep.
+fn json_fields(value: &Value, found: &mut dyn FnMut(&str, &str)) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                match value {
+                    Value::String(text) => found(key, text),
+                    Value::Number(number) => found(key, &number.to_string()),
+                    value => json_fields(value, found),
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                json_fields(value, found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// An output file containing something on the deny list.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DeniedOutput {
+    pub key: String,
+    pub matches: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::deny_list::{DenyList, OutputFormat};
+    use crate::obfuscation::Obfuscation;
+    use crate::test_events::json_event;
+    use crate::types::Event;
+
+    fn pull_request_closed(pull_request: &str) -> Event {
+        let payload = format!(
+            r#""payload": {{"action": "closed", "pull_request": {}}}"#,
+            pull_request
+        );
+        json_event(
+            "PullRequestEvent",
+            "owner",
+            2,
+            &payload,
+            "2017-05-01T07:00:00Z",
+        )
+    }
+
+    #[test]
+    fn denied_events_excluded() {
+        let deny_list =
+            DenyList::from_json(r#"{"logins": ["Someone"], "repo_ids": [55]}"#).unwrap();
+        assert!(deny_list.actor_ids.is_empty());
+
+        let pr = pull_request_closed(r#"{"merged": true, "user": {"id": 5, "login": "someone"}}"#);
+        assert!(deny_list.excludes(&pr));
+
+        // merging or opening an issue is just as much them as authoring
+        let merged = pull_request_closed(
+            r#"{"merged": true, "user": {"id": 6, "login": "owner"},
+            "merged_by": {"id": 5, "login": "SOMEONE"}}"#,
+        );
+        assert!(deny_list.excludes(&merged));
+        let not_merged_by_them = pull_request_closed(
+            r#"{"merged": true, "user": {"id": 6, "login": "owner"},
+            "merged_by": {"id": 7, "login": "someone-else"}}"#,
+        );
+        assert!(!deny_list.excludes(&not_merged_by_them));
+        let issue = json_event(
+            "IssueCommentEvent",
+            "owner",
+            2,
+            r#""payload": {"action": "created", "issue": {"number": 3, "user": {"id": 5, "login": "someone"}}}"#,
+            "2017-05-01T07:00:00Z",
+        );
+        assert!(deny_list.excludes(&issue));
+
+        let mut event = Event::new();
+        event.actor.login = Some("someone-else".to_string());
+        assert!(!deny_list.excludes(&event));
+        event.repo.id = 55;
+        assert!(deny_list.excludes(&event));
+
+        assert_eq!(
+            Some(OutputFormat::Csv),
+            OutputFormat::of_key("rvh2/contributoroverlap/2017/01.csv.gz")
+        );
+        assert_eq!(
+            Some(OutputFormat::Json),
+            OutputFormat::of_key("rvh2/committersketches/2017/01.json.gz")
+        );
+        assert_eq!(
+            None,
+            OutputFormat::of_key("rvh2/committers/2017/quarantine_20170101.json.gz")
+        );
+        assert_eq!(
+            None,
+            OutputFormat::of_key("rvh2/committers/2017/run_summary_20170101.json")
+        );
+    }
+
+    fn deny_list() -> DenyList {
+        DenyList::from_json(r#"{"logins": ["Someone"], "repo_ids": [55]}"#).unwrap()
+    }
+
+    // repo IDs are found whichever column they're in, and only in repo ID columns
+    #[test]
+    fn found_in_sql_by_column() {
+        let sql = "INSERT INTO top_k (metric, bucket, bucket_start, rank, repo_id, value, error) VALUES \
+            ('stars', 'day', '2017-05-01 00:00:00 UTC', 1, 55, 10, 0), \
+            ('stars', 'day', '2017-05-01 00:00:00 UTC', 2, 56, 55, 0) ON CONFLICT DO NOTHING;\n\
+            INSERT INTO fork_edges (parent_repo_id, fork_repo_id, fork_owner, forked_at) VALUES \
+            (1, 55, 'o''brien', '2017-05-01 07:00:00 UTC') ON CONFLICT (fork_repo_id) DO NOTHING;\n\
+            INSERT INTO pull_requests (repo_id, pr_number, author, opened_at, closed_at, merged_at, merged_by, review_comments) VALUES \
+            (2, 55, 'owner', NULL, NULL, NULL, 'someone', 0) ON CONFLICT (repo_id, pr_number) DO NOTHING;";
+        assert_eq!(
+            vec![
+                "fork_edges.fork_repo_id 55".to_string(),
+                "pull_requests.merged_by someone".to_string(),
+                "top_k.repo_id 55".to_string(),
+            ],
+            deny_list()
+                .find_in_output(&Obfuscation::Plaintext, OutputFormat::Sql, sql)
+                .unwrap()
+        );
+        assert!(deny_list()
+            .find_in_output(
+                &Obfuscation::Plaintext,
+                OutputFormat::Sql,
+                "INSERT INTO x (a) VALUES ('"
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn found_in_json_by_key() {
+        let obfuscation = Obfuscation::Hmac(b"secret".to_vec());
+        let sketches = "{\"repo_id\":1,\"estimate\":55}\n{\"estimate\":2,\"repo_id\":55}\n";
+        assert_eq!(
+            vec!["repo_id 55".to_string()],
+            deny_list()
+                .find_in_output(&obfuscation, OutputFormat::Json, sketches)
+                .unwrap()
+        );
+        let open_issues = format!(
+            "[{{\"repo_id\":2,\"number\":55,\"author\":\"{}\",\"opened_at\":\"2017-05-01T07:00:00Z\",\"responded\":false}}]",
+            obfuscation.actor_identity("Someone")
+        );
+        assert_eq!(
+            vec![format!("author {}", obfuscation.actor_identity("Someone"))],
+            deny_list()
+                .find_in_output(&obfuscation, OutputFormat::Json, &open_issues)
+                .unwrap()
+        );
+    }
+
+    // the overlap edge list has the repo as either end of an edge, but not as a count
+    #[test]
+    fn found_in_csv_by_header() {
+        let find = |csv: &str| {
+            deny_list()
+                .find_in_output(&Obfuscation::Plaintext, OutputFormat::Csv, csv)
+                .unwrap()
+        };
+        assert_eq!(
+            vec!["repo_id_a 55".to_string()],
+            find("repo_id_a,repo_id_b,shared_committers\n55,60,2\n")
+        );
+        assert_eq!(
+            vec!["repo_id_b 55".to_string()],
+            find("repo_id_a,repo_id_b,shared_committers\n1,2,3\n40,55,2\n")
+        );
+        assert!(find("repo_id_a,repo_id_b,shared_committers\n1,2,55\n155,255,2\n").is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+use crate::aggregate::Aggregator;
+use crate::obfuscation::Obfuscation;
+use crate::types::Event;
+use chrono::{DateTime, Utc};
+
+/// A repository forked from another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForkEdge {
+    pub parent_repo_id: i64,
+    pub fork_repo_id: i64,
+    /// Obfuscated the same way as committer logins.
+    pub fork_owner: String,
+    pub forked_at: DateTime<Utc>,
+}
+
+impl Event {
+    /// Parent to fork edge for a `ForkEvent` that says which repo it created.
+    pub fn as_fork_edge(&self, obfuscation: &Obfuscation) -> Option<ForkEdge> {
+        if self.event_type != "ForkEvent" || self.repo.id == -1 {
+            return None;
+        }
+        let forkee = self.payload.as_ref()?.forkee.as_ref()?;
+        if forkee.id == -1 {
+            return None;
+        }
+        // The forker is the actor, older payloads don't always include the owner.
+        let owner = forkee
+            .owner
+            .as_ref()
+            .and_then(|owner| owner.login.as_ref())
+            .or(self.actor.login.as_ref())
+            .map(|login| obfuscation.actor_identity(login))
+            .unwrap_or_default();
+        Some(ForkEdge {
+            parent_repo_id: self.repo.id,
+            fork_repo_id: forkee.id,
+            fork_owner: owner,
+            forked_at: self.created_at,
+        })
+    }
+}
+
+/// Parent to fork edges from `ForkEvent`s. Repo IDs join to the repo mapping for names.
+pub struct ForkGraph<'a> {
+    obfuscation: &'a Obfuscation,
+    edges: Vec<ForkEdge>,
+}
+
+impl<'a> ForkGraph<'a> {
+    pub fn new(obfuscation: &'a Obfuscation) -> ForkGraph<'a> {
+        ForkGraph {
+            obfuscation,
+            edges: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Aggregator for ForkGraph<'a> {
+    fn observe(&mut self, event: &Event) {
+        if let Some(edge) = event.as_fork_edge(self.obfuscation) {
+            self.edges.push(edge);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.edges.len()
+    }
+
+    fn drain(&mut self) -> String {
+        let sql = self
+            .edges
+            .chunks(20)
+            .map(|chunk| {
+                let rows = chunk
+                    .iter()
+                    .map(|edge| {
+                        format!(
+                            "({}, {}, '{}', '{}')",
+                            edge.parent_repo_id, edge.fork_repo_id, edge.fork_owner, edge.forked_at
+                        )
+                    })
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                // A repo is only ever forked once.
+                format!("INSERT INTO fork_edges (parent_repo_id, fork_repo_id, fork_owner, forked_at) VALUES {} \
+                    ON CONFLICT (fork_repo_id) DO NOTHING;", rows)
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        self.edges.clear();
+        sql
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregator;
+    use crate::fork_graph::ForkGraph;
+    use crate::obfuscation::Obfuscation;
+    use crate::test_events::json_event;
+    use crate::types::Event;
+
+    #[test]
+    fn fork_event_becomes_edge() {
+        let event = json_event(
+            "ForkEvent",
+            "forker",
+            155,
+            r#""payload": {"forkee": {"id": 900, "name": "bar", "full_name": "forker/bar", "owner": {"id": 7, "login": "forker"}}}"#,
+            "2017-05-01T07:00:00Z",
+        );
+
+        let mut graph = ForkGraph::new(&Obfuscation::Plaintext);
+        graph.observe(&event);
+        graph.observe(&Event::new());
+        assert_eq!(1, graph.len());
+
+        let expected =
+            "INSERT INTO fork_edges (parent_repo_id, fork_repo_id, fork_owner, forked_at) VALUES \
+            (155, 900, 'forker', '2017-05-01 07:00:00 UTC') ON CONFLICT (fork_repo_id) DO NOTHING;";
+        assert_eq!(expected, graph.drain());
+        assert!(graph.is_empty());
+    }
+}
@@ -9,15 +9,62 @@ extern crate serde_json;
 
 use self::flate2::bufread::GzDecoder;
 use self::futures::{Future, Stream};
+use crate::deny_list::DenyList;
+use crate::schema::SchemaObservations;
 use crate::types::*;
-use rusoto_core::{DispatchSignedRequest, ProvideAwsCredentials, Region};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use rusoto_core::Region;
 use rusoto_s3::{GetObjectRequest, ListObjectsV2Request, S3Client, S3};
+use serde::de::DeserializeOwned;
 use std::env;
 use std::io::{BufRead, BufReader};
 use std::{thread, time};
 
 const MAX_PAGE_SIZE: i64 = 500;
 
+/// How many lines of a source file we could and couldn't use.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ParseStats {
+    pub file: String,
+    pub parsed: usize,
+    pub failed: usize,
+    /// Lines with bytes that weren't valid UTF-8, replaced before parsing.
+    pub invalid_utf8: usize,
+    /// Events dropped because they involve something on the deny list.
+    pub excluded: usize,
+    /// Failed lines counted but not kept, over the quarantine's limit per file.
+    pub quarantine_overflow: usize,
+}
+
+/// A line serde rejected, kept so we can see what GHA sent us.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct QuarantinedLine {
+    pub file: String,
+    pub line_number: usize,
+    pub error: String,
+    pub line: String,
+}
+
+/// Events from a single source file along with how parsing it went.
+#[derive(Debug, Clone)]
+pub struct ParsedFile<T> {
+    pub events: Vec<T>,
+    pub stats: ParseStats,
+    pub quarantined: Vec<QuarantinedLine>,
+    /// Only filled in when we're asked to track schema drift.
+    pub schema: SchemaObservations,
+}
+
+impl<T> ParsedFile<T> {
+    /// Keep the first `max` quarantined lines, counting the rest in the stats.
+    pub fn cap_quarantine(&mut self, max: usize) {
+        if self.quarantined.len() > max {
+            self.stats.quarantine_overflow += self.quarantined.len() - max;
+            self.quarantined.truncate(max);
+        }
+    }
+}
+
 /// Get list of files in the bucket, starting with the specified year and up to the number of hours specified.
 pub fn construct_list_of_ingest_files() -> Vec<String> {
     // Get file list from S3:
@@ -103,13 +150,13 @@ pub fn construct_list_of_ingest_files() -> Vec<String> {
 }
 
 /// Download the specified file and parse into pre-2015 events.
-pub fn download_and_parse_old_file<
-    P: ProvideAwsCredentials + Sync + Send + 'static,
-    D: DispatchSignedRequest + Sync + Send + 'static,
->(
+/// Events involving anything on the deny list are dropped.
+pub fn download_and_parse_old_file(
     file_on_s3: &str,
     client: &S3Client,
-) -> Result<Vec<Pre2015Event>, String> {
+    track_schema: bool,
+    deny_list: &DenyList,
+) -> Result<ParsedFile<Pre2015Event>, String> {
     let bucket = env::var("GHABUCKET").expect("Need GHABUCKET set to bucket name");
 
     let get_req = GetObjectRequest {
@@ -141,11 +188,102 @@ pub fn download_and_parse_old_file<
 
     // convert the Vec<u8> into a slice for the GzDecoder:
     let decoder = GzDecoder::new(&read_body[..]);
-    parse_ze_file_2014_older(BufReader::new(decoder))
+    let mut parsed = parse_ze_file_2014_older(file_on_s3, BufReader::new(decoder), track_schema)?;
+    let before = parsed.events.len();
+    parsed.events.retain(|event| !deny_list.excludes_old(event));
+    parsed.stats.excluded = before - parsed.events.len();
+    Ok(parsed)
+}
+
+/// Date and hour of a source file, which are named for their hour like `2016-01-01-15.json.gz`,
+/// to sort on. Files that aren't named like that sort by name, before any that are.
+fn hour_sort_key(file_on_s3: &str) -> (String, Option<u32>) {
+    let stem = file_on_s3.split('.').next().unwrap_or(file_on_s3);
+    let hour = stem.rfind('-').and_then(|dash| {
+        stem[dash + 1..]
+            .parse::<u32>()
+            .ok()
+            .map(|hour| (dash, hour))
+    });
+    match hour {
+        Some((dash, hour)) => (stem[..dash].to_string(), Some(hour)),
+        None => (file_on_s3.to_string(), None),
+    }
+}
+
+/// Put source files in the order of the hours they cover. S3 lists them by name, which puts
+/// `2016-01-01-10` before `2016-01-01-2`.
+pub fn sort_chronologically(files: &mut [String]) {
+    files.sort_by(|a, b| {
+        hour_sort_key(a)
+            .cmp(&hour_sort_key(b))
+            .then_with(|| a.cmp(b))
+    });
+}
+
+/// The hour a source file named like `2016-01-01-15.json.gz` covers.
+pub fn file_hour(file_on_s3: &str) -> Option<DateTime<Utc>> {
+    let (date, hour) = hour_sort_key(file_on_s3);
+    let hour = NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+        .ok()?
+        .and_hms_opt(hour?, 0, 0)?;
+    Some(DateTime::from_utc(hour, Utc))
+}
+
+/// From the start of the first file's hour to the end of the last one's, for files sorted
+/// chronologically.
+pub fn covered_hours(files: &[String]) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let start = file_hour(files.first()?)?;
+    let end = file_hour(files.last()?)? + Duration::hours(1);
+    Some((start, end))
+}
+
+fn is_pre_2015_file(file_on_s3: &str) -> bool {
+    match file_on_s3.get(..4).map(str::parse::<i32>) {
+        Some(Ok(year)) => year < 2015,
+        _ => false,
+    }
+}
+
+/// Download the specified file from any year and parse it into 2015 and later style events.
+/// Pre-2015 events that can't be converted count as failed lines.
+pub fn download_and_parse_any_file(
+    file_on_s3: &str,
+    client: &S3Client,
+    track_schema: bool,
+    deny_list: &DenyList,
+) -> Result<ParsedFile<Event>, String> {
+    if !is_pre_2015_file(file_on_s3) {
+        return download_and_parse_file(file_on_s3, client, track_schema, deny_list);
+    }
+    // The known fields are for 2015 and later events, every old one would look like drift.
+    let old = download_and_parse_old_file(file_on_s3, client, false, deny_list)?;
+    let mut stats = old.stats;
+    let events: Vec<Event> = old
+        .events
+        .iter()
+        .filter_map(Pre2015Event::to_event)
+        .collect();
+    let unconverted = old.events.len() - events.len();
+    stats.parsed -= unconverted;
+    stats.failed += unconverted;
+    Ok(ParsedFile {
+        events,
+        stats,
+        quarantined: old.quarantined,
+        schema: old.schema,
+    })
 }
 
 /// Download the specified file and parse into 2015 and later events.
-pub fn download_and_parse_file(file_on_s3: &str, client: &S3Client) -> Result<Vec<Event>, String> {
+/// If `track_schema` is set the fields of every event are recorded as well.
+/// Events involving anything on the deny list are dropped.
+pub fn download_and_parse_file(
+    file_on_s3: &str,
+    client: &S3Client,
+    track_schema: bool,
+    deny_list: &DenyList,
+) -> Result<ParsedFile<Event>, String> {
     let bucket = env::var("GHABUCKET").expect("Need GHABUCKET set to bucket name");
 
     let get_req = GetObjectRequest {
@@ -177,35 +315,188 @@ pub fn download_and_parse_file(file_on_s3: &str, client: &S3Client) -> Result<Ve
 
     // conver the Vec<u8> into a slice for GzDecoder:
     let decoder = GzDecoder::new(&read_body[..]);
-    parse_ze_file_2015_newer(BufReader::new(decoder))
+    let mut parsed = parse_ze_file_2015_newer(file_on_s3, BufReader::new(decoder), track_schema)?;
+    let before = parsed.events.len();
+    parsed.events.retain(|event| !deny_list.excludes(event));
+    parsed.stats.excluded = before - parsed.events.len();
+    Ok(parsed)
 }
 
 /// Deserialize pre-2015 events
-fn parse_ze_file_2014_older<R: BufRead>(mut contents: R) -> Result<Vec<Pre2015Event>, String> {
-    let mut events: Vec<Pre2015Event> = Vec::new();
-    let mut line = String::new();
-    while contents.read_line(&mut line).unwrap() > 0 {
-        match serde_json::from_str(&line) {
-            Ok(event) => events.push(event),
-            Err(err) => warn!("Found a weird line of json, got this error: {:?} for line {}.", err, line),
+fn parse_ze_file_2014_older<R: BufRead>(
+    file_name: &str,
+    contents: R,
+    track_schema: bool,
+) -> Result<ParsedFile<Pre2015Event>, String> {
+    parse_ze_file(file_name, contents, track_schema)
+}
+
+/// Deserialize 2015 and later events
+fn parse_ze_file_2015_newer<R: BufRead>(
+    file_name: &str,
+    contents: R,
+    track_schema: bool,
+) -> Result<ParsedFile<Event>, String> {
+    parse_ze_file(file_name, contents, track_schema)
+}
+
+/// Deserialize one event per line. Lines serde rejects are quarantined instead of dropped and
+/// invalid UTF-8 is replaced so a single bad byte doesn't cost us the whole file.
+/// Only a failure to read the file itself, such as a truncated gzip stream, is an error.
+fn parse_ze_file<T: DeserializeOwned, R: BufRead>(
+    file_name: &str,
+    mut contents: R,
+    track_schema: bool,
+) -> Result<ParsedFile<T>, String> {
+    let mut parsed = ParsedFile {
+        events: Vec::new(),
+        stats: ParseStats {
+            file: file_name.to_string(),
+            ..Default::default()
+        },
+        quarantined: Vec::new(),
+        schema: SchemaObservations::default(),
+    };
+    let mut raw_line: Vec<u8> = Vec::new();
+    let mut line_number = 0;
+    loop {
+        raw_line.clear();
+        match contents.read_until(b'\n', &mut raw_line) {
+            Ok(0) => break,
+            Ok(_) => line_number += 1,
+            Err(err) => {
+                return Err(format!(
+                    "Couldn't read {} after line {}: {:?}",
+                    file_name, line_number, err
+                ))
+            }
+        }
+        let line = match String::from_utf8(raw_line.clone()) {
+            Ok(line) => line,
+            Err(_) => {
+                parsed.stats.invalid_utf8 += 1;
+                String::from_utf8_lossy(&raw_line).into_owned()
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = if track_schema {
+            // Going through a Value lets us see every field, not just the ones we keep.
+            serde_json::from_str::<serde_json::Value>(&line).and_then(|value| {
+                parsed.schema.observe(&value);
+                serde_json::from_value(value)
+            })
+        } else {
+            serde_json::from_str(&line)
+        };
+        match event {
+            Ok(event) => {
+                parsed.stats.parsed += 1;
+                parsed.events.push(event);
+            }
+            Err(err) => {
+                warn!(
+                    "Found a weird line of json in {} at line {}, got this error: {:?}",
+                    file_name, line_number, err
+                );
+                parsed.stats.failed += 1;
+                parsed.quarantined.push(QuarantinedLine {
+                    file: file_name.to_string(),
+                    line_number,
+                    error: err.to_string(),
+                    line: line.trim_end().to_string(),
+                });
+            }
         };
-        line.clear();
     }
 
-    Ok(events)
+    Ok(parsed)
 }
 
-/// Deserialize 2015 and later events
-fn parse_ze_file_2015_newer<R: BufRead>(mut contents: R) -> Result<Vec<Event>, String> {
-    let mut events: Vec<Event> = Vec::new();
-    let mut line = String::new();
-    while contents.read_line(&mut line).unwrap() > 0 {
-        match serde_json::from_str(&line) {
-            Ok(event) => events.push(event),
-            Err(err) => warn!("Found a weird line of json, got this error: {:?} for line {}.", err, line),
-        };
-        line.clear();
+#[cfg(test)]
+mod tests {
+    use crate::gha_sources::{
+        covered_hours, file_hour, is_pre_2015_file, parse_ze_file_2015_newer, sort_chronologically,
+    };
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn files_sorted_by_hour() {
+        let mut files: Vec<String> = vec![
+            "2016-01-02-0.json.gz",
+            "2016-01-01-1.json.gz",
+            "2016-01-01-10.json.gz",
+            "2016-01-01-2.json.gz",
+            "2016-01-01-0.json.gz",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        sort_chronologically(&mut files);
+        assert_eq!(
+            vec![
+                "2016-01-01-0.json.gz",
+                "2016-01-01-1.json.gz",
+                "2016-01-01-2.json.gz",
+                "2016-01-01-10.json.gz",
+                "2016-01-02-0.json.gz",
+            ],
+            files
+        );
+        assert_eq!(
+            Some((
+                Utc.ymd(2016, 1, 1).and_hms(0, 0, 0),
+                Utc.ymd(2016, 1, 2).and_hms(1, 0, 0)
+            )),
+            covered_hours(&files)
+        );
+        assert_eq!(None, file_hour("index.html"));
     }
 
-    Ok(events)
+    #[test]
+    fn pre_2015_files() {
+        assert!(is_pre_2015_file("2011-02-12-0.json.gz"));
+        assert!(is_pre_2015_file("2014-12-31-23.json.gz"));
+        assert!(!is_pre_2015_file("2015-01-01-0.json.gz"));
+        assert!(!is_pre_2015_file("x.gz"));
+    }
+
+    #[test]
+    fn bad_lines_are_quarantined() {
+        let mut contents: Vec<u8> = Vec::new();
+        contents.extend_from_slice(br#"{"id": "1", "type": "PushEvent", "actor": {"id": 1, "login": "a"}, "repo": {"id": 2, "name": "a/b"}, "created_at": "2017-05-01T07:00:00Z"}"#);
+        contents.push(b'\n');
+        contents.extend_from_slice(b"{\"id\": \"2\", \"type\": \"PushEvent\"}\n");
+        // invalid UTF-8 in the login is replaced, the event still parses
+        contents.extend_from_slice(
+            br#"{"id": "3", "type": "PushEvent", "actor": {"id": 1, "login": "a"#,
+        );
+        contents.push(0xff);
+        contents.extend_from_slice(
+            br#""}, "repo": {"id": 2, "name": "a/b"}, "created_at": "2017-05-01T07:00:00Z"}"#,
+        );
+        contents.push(b'\n');
+
+        let mut parsed =
+            parse_ze_file_2015_newer("2017-05-01-7.json.gz", &contents[..], false).unwrap();
+
+        assert_eq!(2, parsed.events.len());
+        assert_eq!(2, parsed.stats.parsed);
+        assert_eq!(1, parsed.stats.failed);
+        assert_eq!(1, parsed.stats.invalid_utf8);
+        assert_eq!(1, parsed.quarantined.len());
+        assert_eq!(2, parsed.quarantined[0].line_number);
+        assert_eq!("2017-05-01-7.json.gz", parsed.quarantined[0].file);
+        assert_eq!(3, parsed.events[1].id);
+
+        parsed.cap_quarantine(1);
+        assert_eq!(
+            (1, 0),
+            (parsed.quarantined.len(), parsed.stats.quarantine_overflow)
+        );
+        parsed.cap_quarantine(0);
+        assert!(parsed.quarantined.is_empty());
+        assert_eq!(1, parsed.stats.quarantine_overflow);
+    }
 }
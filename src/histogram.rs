@@ -0,0 +1,97 @@
+use crate::aggregate::Aggregator;
+use crate::time_bucket::TimeBucket;
+use crate::types::Event;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+/// How many of each event type every repo had per hour: an activity index we can query instead
+/// of going back to the archive.
+///
+/// Counts are added to what's already in the table, so loading the same hours twice counts them twice.
+#[derive(Debug, Clone, Default)]
+pub struct EventTypeHistogram {
+    counts: BTreeMap<(i64, DateTime<Utc>, String), u64>,
+}
+
+impl EventTypeHistogram {
+    pub fn count(&self, repo_id: i64, at: DateTime<Utc>, event_type: &str) -> u64 {
+        self.counts
+            .get(&(repo_id, TimeBucket::Hour.start(at), event_type.to_string()))
+            .cloned()
+            .unwrap_or(0)
+    }
+}
+
+impl Aggregator for EventTypeHistogram {
+    fn observe(&mut self, event: &Event) {
+        if event.repo.id == -1 {
+            return;
+        }
+        *self
+            .counts
+            .entry((
+                event.repo.id,
+                TimeBucket::Hour.start(event.created_at),
+                event.event_type.clone(),
+            ))
+            .or_insert(0) += 1;
+    }
+
+    fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    fn drain(&mut self) -> String {
+        let rows: Vec<String> = self
+            .counts
+            .iter()
+            .map(|((repo_id, hour, event_type), events)| {
+                format!("({}, '{}', '{}', {})", repo_id, hour, event_type, events)
+            })
+            .collect();
+        self.counts.clear();
+        rows.chunks(20)
+            .map(|chunk| {
+                format!(
+                    "INSERT INTO event_type_counts (repo_id, hour, event_type, events) VALUES {} \
+                    ON CONFLICT (repo_id, hour, event_type) DO UPDATE SET \
+                    events = event_type_counts.events + EXCLUDED.events;",
+                    chunk.join(", ")
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregator;
+    use crate::histogram::EventTypeHistogram;
+    use crate::test_events::event_for;
+    use crate::types::Event;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn at_minute(minute: u32) -> DateTime<Utc> {
+        Utc.ymd(2012, 3, 10).and_hms(22, minute, 0)
+    }
+
+    #[test]
+    fn counts_per_repo_hour_and_type() {
+        let mut histogram = EventTypeHistogram::default();
+        histogram.observe(&event_for("PushEvent", 155, at_minute(1)));
+        histogram.observe(&event_for("PushEvent", 155, at_minute(59)));
+        histogram.observe(&event_for("WatchEvent", 155, at_minute(30)));
+        histogram.observe(&Event::new());
+
+        let hour = Utc.ymd(2012, 3, 10).and_hms(22, 0, 0);
+        assert_eq!(2, histogram.count(155, hour, "PushEvent"));
+        assert_eq!(1, histogram.count(155, hour, "WatchEvent"));
+
+        let expected = "INSERT INTO event_type_counts (repo_id, hour, event_type, events) VALUES \
+            (155, '2012-03-10 22:00:00 UTC', 'PushEvent', 2), (155, '2012-03-10 22:00:00 UTC', 'WatchEvent', 1) \
+            ON CONFLICT (repo_id, hour, event_type) DO UPDATE SET \
+            events = event_type_counts.events + EXCLUDED.events;";
+        assert_eq!(expected, histogram.drain());
+    }
+}
@@ -0,0 +1,373 @@
+use crate::aggregate::Aggregator;
+use crate::obfuscation::Obfuscation;
+use crate::time_bucket::TimeBucket;
+use crate::types::Event;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+/// A HyperLogLog sketch: estimates how many distinct values it's been given in a fixed amount of
+/// memory, 2^precision bytes.
+///
+/// Values are hashed with MD5 so sketches built by different runs agree and can be merged. The
+/// standard error is about 1.04 / sqrt(2^precision), 1.6% at precision 12.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub const MIN_PRECISION: u8 = 4;
+    pub const MAX_PRECISION: u8 = 16;
+
+    pub fn new(precision: u8) -> HyperLogLog {
+        assert!(
+            (HyperLogLog::MIN_PRECISION..=HyperLogLog::MAX_PRECISION).contains(&precision),
+            "HyperLogLog precision must be between {} and {}",
+            HyperLogLog::MIN_PRECISION,
+            HyperLogLog::MAX_PRECISION
+        );
+        HyperLogLog {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    pub fn insert(&mut self, value: &str) {
+        let digest = md5::compute(value.as_bytes());
+        let mut first_eight = [0u8; 8];
+        first_eight.copy_from_slice(&digest[..8]);
+        let hash = u64::from_be_bytes(first_eight);
+
+        let index = (hash >> (64 - self.precision)) as usize;
+        let rest = hash << self.precision;
+        // Position of the first set bit in what's left of the hash, capped when it's all zeroes.
+        let rank = (rest.leading_zeros() + 1).min(u32::from(64 - self.precision) + 1) as u8;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    /// Folds another sketch in, as if every value it saw had been inserted here.
+    pub fn merge(&mut self, other: &HyperLogLog) -> Result<(), String> {
+        if other.precision != self.precision {
+            return Err(format!(
+                "Can't merge a precision {} sketch into a precision {} one",
+                other.precision, self.precision
+            ));
+        }
+        for (register, theirs) in self.registers.iter_mut().zip(&other.registers) {
+            if *theirs > *register {
+                *register = *theirs;
+            }
+        }
+        Ok(())
+    }
+
+    /// Estimated number of distinct values inserted.
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|register| 2f64.powi(-i32::from(*register)))
+            .sum();
+        let raw = alpha * m * m / sum;
+        let zeroes = self
+            .registers
+            .iter()
+            .filter(|register| **register == 0)
+            .count();
+        // Linear counting is more accurate while most registers are still empty.
+        let estimate = if raw <= 2.5 * m && zeroes > 0 {
+            m * (m / zeroes as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as u64
+    }
+
+    /// Registers as hex, two characters each.
+    pub fn to_hex(&self) -> String {
+        self.registers
+            .iter()
+            .map(|register| format!("{:02x}", register))
+            .collect()
+    }
+
+    /// Only the registers that are set, as four hex characters of index and two of value each.
+    /// Much shorter than `to_hex` while few values have been inserted.
+    pub fn to_sparse_hex(&self) -> String {
+        self.registers
+            .iter()
+            .enumerate()
+            .filter(|(_, register)| **register != 0)
+            .map(|(index, register)| format!("{:04x}{:02x}", index, register))
+            .collect()
+    }
+
+    pub fn from_sparse_hex(precision: u8, hex: &str) -> Result<HyperLogLog, String> {
+        let mut sketch = HyperLogLog::new(precision);
+        for chunk in hex.as_bytes().chunks(6) {
+            // Only the last chunk can be short.
+            if chunk.len() != 6 {
+                return Err(format!(
+                    "Expected six hex characters per register, got {}",
+                    hex.len()
+                ));
+            }
+            let chunk = std::str::from_utf8(chunk).map_err(|e| e.to_string())?;
+            let index = usize::from_str_radix(&chunk[..4], 16).map_err(|e| e.to_string())?;
+            let register = u8::from_str_radix(&chunk[4..], 16).map_err(|e| e.to_string())?;
+            if index >= sketch.registers.len() {
+                return Err(format!(
+                    "Register {} is out of range for precision {}",
+                    index, precision
+                ));
+            }
+            sketch.registers[index] = register;
+        }
+        Ok(sketch)
+    }
+
+    pub fn from_hex(precision: u8, hex: &str) -> Result<HyperLogLog, String> {
+        let mut sketch = HyperLogLog::new(precision);
+        if hex.len() != sketch.registers.len() * 2 {
+            return Err(format!(
+                "Expected {} hex characters for precision {}, got {}",
+                sketch.registers.len() * 2,
+                precision,
+                hex.len()
+            ));
+        }
+        for (register, chunk) in sketch.registers.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let chunk = std::str::from_utf8(chunk).map_err(|e| e.to_string())?;
+            *register = u8::from_str_radix(chunk, 16).map_err(|e| e.to_string())?;
+        }
+        Ok(sketch)
+    }
+}
+
+/// How a sketch's registers are written out.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SketchEncoding {
+    /// See `HyperLogLog::to_hex`.
+    Dense,
+    /// See `HyperLogLog::to_sparse_hex`.
+    Sparse,
+}
+
+/// One line of committer sketch output.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommitterSketch {
+    pub repo_id: i64,
+    pub bucket_start: DateTime<Utc>,
+    pub precision: u8,
+    pub encoding: SketchEncoding,
+    pub registers: String,
+    /// Distinct committers according to this sketch alone.
+    pub estimate: u64,
+}
+
+impl CommitterSketch {
+    /// Written whichever way is shorter.
+    pub fn new(repo_id: i64, bucket_start: DateTime<Utc>, sketch: &HyperLogLog) -> CommitterSketch {
+        let sparse = sketch.to_sparse_hex();
+        let (encoding, registers) = if sparse.len() < sketch.registers.len() * 2 {
+            (SketchEncoding::Sparse, sparse)
+        } else {
+            (SketchEncoding::Dense, sketch.to_hex())
+        };
+        CommitterSketch {
+            repo_id,
+            bucket_start,
+            precision: sketch.precision(),
+            encoding,
+            registers,
+            estimate: sketch.estimate(),
+        }
+    }
+
+    pub fn sketch(&self) -> Result<HyperLogLog, String> {
+        match self.encoding {
+            SketchEncoding::Dense => HyperLogLog::from_hex(self.precision, &self.registers),
+            SketchEncoding::Sparse => HyperLogLog::from_sparse_hex(self.precision, &self.registers),
+        }
+    }
+}
+
+/// A HyperLogLog sketch of the committers to each repo per time bucket, counted the same way as
+/// the committer count mode. Committers go in by their obfuscated identity, so a sketch can't be
+/// checked for a login without the obfuscation key.
+///
+/// Output is one JSON `CommitterSketch` per line. The same repo and bucket can show up in more
+/// than one line, or more than one run: merge their sketches to get the distinct committers over
+/// any set of buckets. Sketches only merge correctly when they were built with the same
+/// obfuscation and key.
+pub struct CommitterSketches<'a> {
+    obfuscation: &'a Obfuscation,
+    bucket: TimeBucket,
+    precision: u8,
+    sketches: BTreeMap<(i64, DateTime<Utc>), HyperLogLog>,
+}
+
+impl<'a> CommitterSketches<'a> {
+    pub fn new(
+        obfuscation: &'a Obfuscation,
+        bucket: TimeBucket,
+        precision: u8,
+    ) -> CommitterSketches<'a> {
+        CommitterSketches {
+            obfuscation,
+            bucket,
+            precision,
+            sketches: BTreeMap::new(),
+        }
+    }
+}
+
+impl<'a> Aggregator for CommitterSketches<'a> {
+    fn observe(&mut self, event: &Event) {
+        if !event.is_commit_event() {
+            return;
+        }
+        let commit_event = event.as_commit_event();
+        if commit_event.actor.is_empty() || commit_event.repo_id == -1 {
+            return;
+        }
+        let precision = self.precision;
+        self.sketches
+            .entry((commit_event.repo_id, self.bucket.start(event.created_at)))
+            .or_insert_with(|| HyperLogLog::new(precision))
+            .insert(&self.obfuscation.actor_identity(&commit_event.actor));
+    }
+
+    fn len(&self) -> usize {
+        self.sketches.len()
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "json"
+    }
+
+    fn drain(&mut self) -> String {
+        let lines: Vec<String> = self
+            .sketches
+            .iter()
+            .map(|((repo_id, bucket_start), sketch)| {
+                let line = CommitterSketch::new(*repo_id, *bucket_start, sketch);
+                serde_json::to_string(&line).expect("Couldn't serialize committer sketch")
+            })
+            .collect();
+        self.sketches.clear();
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregator;
+    use crate::hll::{CommitterSketch, CommitterSketches, HyperLogLog, SketchEncoding};
+    use crate::obfuscation::Obfuscation;
+    use crate::time_bucket::TimeBucket;
+    use crate::types::{Event, Payload};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn estimates_and_merges() {
+        let mut evens = HyperLogLog::new(12);
+        let mut odds = HyperLogLog::new(12);
+        let mut all = HyperLogLog::new(12);
+        for i in 0..20_000 {
+            let login = format!("committer{}", i);
+            if i % 2 == 0 {
+                evens.insert(&login);
+            } else {
+                odds.insert(&login);
+            }
+            all.insert(&login);
+            // seeing someone again doesn't change anything
+            all.insert(&login);
+        }
+
+        let estimate = all.estimate() as f64;
+        assert!(
+            (estimate - 20_000.0).abs() < 20_000.0 * 0.05,
+            "{}",
+            estimate
+        );
+
+        evens.merge(&odds).unwrap();
+        assert_eq!(all, evens);
+        assert!(evens.merge(&HyperLogLog::new(10)).is_err());
+
+        assert_eq!(Ok(all.clone()), HyperLogLog::from_hex(12, &all.to_hex()));
+        assert!(HyperLogLog::from_hex(10, &all.to_hex()).is_err());
+        assert_eq!(
+            Ok(all.clone()),
+            HyperLogLog::from_sparse_hex(12, &all.to_sparse_hex())
+        );
+        assert!(HyperLogLog::from_sparse_hex(12, "00010").is_err());
+        assert_eq!(0, HyperLogLog::new(12).estimate());
+    }
+
+    fn push(login: &str, day: u32) -> Event {
+        let mut event = Event::new();
+        event.id = 1;
+        event.event_type = "PushEvent".to_string();
+        event.actor.id = 1;
+        event.actor.login = Some(login.to_string());
+        event.repo.id = 155;
+        event.payload = Some(Payload {
+            push_id: Some(1),
+            size: Some(1),
+            ..Default::default()
+        });
+        event.created_at = Utc.ymd(2017, 5, day).and_hms(7, 0, 0);
+        event
+    }
+
+    #[test]
+    fn sketches_per_repo_and_bucket() {
+        let obfuscation = Obfuscation::Hmac(b"secret".to_vec());
+        let mut sketches = CommitterSketches::new(&obfuscation, TimeBucket::Day, 8);
+        sketches.observe(&push("alice", 1));
+        sketches.observe(&push("bob", 1));
+        sketches.observe(&push("alice", 1));
+        sketches.observe(&push("alice", 2));
+        sketches.observe(&Event::new());
+        assert_eq!(2, sketches.len());
+
+        let output = sketches.drain();
+        assert!(sketches.is_empty());
+        let lines: Vec<CommitterSketch> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(2, lines.len());
+        assert_eq!(Utc.ymd(2017, 5, 1).and_hms(0, 0, 0), lines[0].bucket_start);
+        assert_eq!(2, lines[0].estimate);
+        assert_eq!(1, lines[1].estimate);
+        assert_eq!(SketchEncoding::Sparse, lines[0].encoding);
+
+        // the sketch holds the obfuscated identity, not the login
+        let mut alice = HyperLogLog::new(8);
+        alice.insert(&obfuscation.actor_identity("alice"));
+        assert_eq!(Ok(alice), lines[1].sketch());
+
+        let mut both_days = lines[0].sketch().unwrap();
+        both_days.merge(&lines[1].sketch().unwrap()).unwrap();
+        assert_eq!(2, both_days.estimate());
+    }
+}
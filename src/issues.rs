@@ -0,0 +1,419 @@
+extern crate serde_json;
+
+use crate::aggregate::Aggregator;
+use crate::obfuscation::Obfuscation;
+use crate::time_bucket::TimeBucket;
+use crate::types::{Event, Issue};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::BTreeMap;
+
+/// An issue we've seen opened but not yet answered or closed, carried from one run to the next.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OpenIssue {
+    pub repo_id: i64,
+    pub number: i64,
+    /// Obfuscated like committer logins.
+    pub author: Option<String>,
+    pub opened_at: DateTime<Utc>,
+    pub responded: bool,
+    /// Set once it's been closed and reopened, after which closing it again isn't counted.
+    #[serde(default)]
+    pub reopened_at: Option<DateTime<Utc>>,
+}
+
+/// Issue counts for a repo for the month the issues were opened. Everything adds up so they can
+/// be summed across flushes and runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IssueResponseStats {
+    pub opened: u64,
+    /// Issues that got a comment from someone other than the author.
+    pub responded: u64,
+    /// Total over responded issues, divide by `responded` for the mean.
+    pub seconds_to_first_response: i64,
+    pub closed: u64,
+    pub seconds_to_close: i64,
+}
+
+/// Time to first response and time to close for issues, from `IssuesEvent`s and
+/// `IssueCommentEvent`s keyed by repo ID and issue number, per repo per month opened.
+///
+/// Issues still waiting for a first response are kept in `open_issues`, which main saves and
+/// loads so an issue opened in one run and answered or closed in a later one is still counted.
+/// Answered issues are let go: their close is worked out from the issue in the close event.
+/// Reopened issues are kept until they're closed again so that close isn't counted twice, and
+/// time to close is always to the first close.
+pub struct IssueResponsiveness<'a> {
+    obfuscation: &'a Obfuscation,
+    open_issues: BTreeMap<(i64, i64), OpenIssue>,
+    stats: BTreeMap<(i64, DateTime<Utc>), IssueResponseStats>,
+    latest_event_at: Option<DateTime<Utc>>,
+}
+
+impl<'a> IssueResponsiveness<'a> {
+    pub fn new(obfuscation: &'a Obfuscation) -> IssueResponsiveness<'a> {
+        IssueResponsiveness {
+            obfuscation,
+            open_issues: BTreeMap::new(),
+            stats: BTreeMap::new(),
+            latest_event_at: None,
+        }
+    }
+
+    /// Pick up where a previous run left off with the issues it had open.
+    pub fn with_open_issues(mut self, json: &str) -> Result<IssueResponsiveness<'a>, String> {
+        let open_issues: Vec<OpenIssue> =
+            serde_json::from_str(json).map_err(|e| format!("Couldn't parse open issues: {}", e))?;
+        for issue in open_issues {
+            self.open_issues
+                .insert((issue.repo_id, issue.number), issue);
+        }
+        Ok(self)
+    }
+
+    pub fn open_issues_json(&self) -> String {
+        let open_issues: Vec<&OpenIssue> = self.open_issues.values().collect();
+        serde_json::to_string(&open_issues).expect("Couldn't serialize open issues")
+    }
+
+    pub fn open_issue_count(&self) -> usize {
+        self.open_issues.len()
+    }
+
+    /// Let go of open issues opened, or reopened, more than `max_age` before the newest event
+    /// seen, which nobody is going to answer now. Returns how many there were.
+    pub fn expire(&mut self, max_age: Duration) -> usize {
+        let latest_event_at = match self.latest_event_at {
+            Some(latest_event_at) => latest_event_at,
+            None => return 0,
+        };
+        let open_issues = self.open_issues.len();
+        self.open_issues.retain(|_, issue| {
+            latest_event_at - issue.reopened_at.unwrap_or(issue.opened_at) <= max_age
+        });
+        open_issues - self.open_issues.len()
+    }
+
+    pub fn stats(&self, repo_id: i64, opened_at: DateTime<Utc>) -> IssueResponseStats {
+        self.stats
+            .get(&(repo_id, TimeBucket::Month.start(opened_at)))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn identity(&self, login: Option<&String>) -> Option<String> {
+        login.map(|login| self.obfuscation.actor_identity(login))
+    }
+
+    /// The open issue, or one made up from the payload for an issue opened before we started.
+    fn open_issue(&self, event: &Event, number: i64, issue: &Issue) -> OpenIssue {
+        match self.open_issues.get(&(event.repo.id, number)) {
+            Some(open_issue) => open_issue.clone(),
+            None => OpenIssue {
+                repo_id: event.repo.id,
+                number,
+                author: self.identity(issue.user.as_ref().and_then(|user| user.login.as_ref())),
+                opened_at: issue.created_at.unwrap_or(event.created_at),
+                // The comment count includes this one, so more than that means we missed some.
+                responded: issue.comments.unwrap_or(0) > 1,
+                reopened_at: None,
+            },
+        }
+    }
+
+    fn stats_for(&mut self, open_issue: &OpenIssue) -> &mut IssueResponseStats {
+        self.stats
+            .entry((
+                open_issue.repo_id,
+                TimeBucket::Month.start(open_issue.opened_at),
+            ))
+            .or_default()
+    }
+}
+
+impl<'a> Aggregator for IssueResponsiveness<'a> {
+    fn observe(&mut self, event: &Event) {
+        let is_comment = event.event_type == "IssueCommentEvent";
+        if (!is_comment && event.event_type != "IssuesEvent") || event.repo.id == -1 {
+            return;
+        }
+        let payload = match event.payload {
+            Some(ref payload) => payload,
+            None => return,
+        };
+        let issue = match payload.issue {
+            // Comments on pull requests come through as issue comments too.
+            Some(ref issue) if issue.pull_request.is_none() => issue,
+            _ => return,
+        };
+        let number = match issue.number {
+            Some(number) => number,
+            None => return,
+        };
+        let key = (event.repo.id, number);
+        if self.latest_event_at < Some(event.created_at) {
+            self.latest_event_at = Some(event.created_at);
+        }
+
+        match (is_comment, payload.action.as_deref()) {
+            (false, Some("opened")) => {
+                let mut open_issue = self.open_issue(event, number, issue);
+                open_issue.responded = false;
+                self.stats_for(&open_issue).opened += 1;
+                self.open_issues.insert(key, open_issue);
+            }
+            (false, Some("reopened")) => {
+                let mut open_issue = self.open_issue(event, number, issue);
+                open_issue.reopened_at = Some(event.created_at);
+                self.open_issues.insert(key, open_issue);
+            }
+            (false, Some("closed")) => {
+                let open_issue = self.open_issue(event, number, issue);
+                self.open_issues.remove(&key);
+                if open_issue.reopened_at.is_some() {
+                    return;
+                }
+                let closed_at = issue.closed_at.unwrap_or(event.created_at);
+                let stats = self.stats_for(&open_issue);
+                stats.closed += 1;
+                stats.seconds_to_close += (closed_at - open_issue.opened_at).num_seconds();
+            }
+            (true, Some("created")) => {
+                if issue.state.as_deref() == Some("closed") {
+                    return;
+                }
+                let mut open_issue = self.open_issue(event, number, issue);
+                let commenter = self.identity(event.actor.login.as_ref());
+                if !open_issue.responded && commenter.is_some() && commenter != open_issue.author {
+                    open_issue.responded = true;
+                    let stats = self.stats_for(&open_issue);
+                    stats.responded += 1;
+                    stats.seconds_to_first_response +=
+                        (event.created_at - open_issue.opened_at).num_seconds();
+                }
+                if open_issue.responded && open_issue.reopened_at.is_none() {
+                    self.open_issues.remove(&key);
+                } else {
+                    self.open_issues.insert(key, open_issue);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.stats.len()
+    }
+
+    /// Only the stats are drained, open issues stay until they're closed.
+    fn drain(&mut self) -> String {
+        let rows: Vec<String> = self
+            .stats
+            .iter()
+            .map(|((repo_id, month), stats)| {
+                format!(
+                    "({}, '{}', {}, {}, {}, {}, {})",
+                    repo_id,
+                    month,
+                    stats.opened,
+                    stats.responded,
+                    stats.seconds_to_first_response,
+                    stats.closed,
+                    stats.seconds_to_close
+                )
+            })
+            .collect();
+        self.stats.clear();
+        rows.chunks(20)
+            .map(|chunk| {
+                format!("INSERT INTO issue_responsiveness (repo_id, month, opened, responded, seconds_to_first_response, closed, seconds_to_close) VALUES {} \
+                    ON CONFLICT (repo_id, month) DO UPDATE SET \
+                    opened = issue_responsiveness.opened + EXCLUDED.opened, \
+                    responded = issue_responsiveness.responded + EXCLUDED.responded, \
+                    seconds_to_first_response = issue_responsiveness.seconds_to_first_response + EXCLUDED.seconds_to_first_response, \
+                    closed = issue_responsiveness.closed + EXCLUDED.closed, \
+                    seconds_to_close = issue_responsiveness.seconds_to_close + EXCLUDED.seconds_to_close;", chunk.join(", "))
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregator;
+    use crate::issues::IssueResponsiveness;
+    use crate::obfuscation::Obfuscation;
+    use crate::test_events::json_event;
+    use crate::types::Event;
+    use chrono::{Duration, TimeZone, Utc};
+
+    fn issue_event(
+        event_type: &str,
+        action: &str,
+        actor: &str,
+        issue: &str,
+        created_at: &str,
+    ) -> Event {
+        let payload = format!(
+            r#""payload": {{"action": "{}", "issue": {}}}"#,
+            action, issue
+        );
+        json_event(event_type, actor, 155, &payload, created_at)
+    }
+
+    #[test]
+    fn response_and_close_across_runs() {
+        let issue = r#"{"number": 3, "user": {"id": 1, "login": "author"}, "state": "open", "comments": 1,
+            "created_at": "2017-05-01T07:00:00Z"}"#;
+        let obfuscation = Obfuscation::Plaintext;
+        let mut first_run = IssueResponsiveness::new(&obfuscation);
+        first_run.observe(&issue_event(
+            "IssuesEvent",
+            "opened",
+            "author",
+            issue,
+            "2017-05-01T07:00:00Z",
+        ));
+        // the author answering themselves isn't a response
+        first_run.observe(&issue_event(
+            "IssueCommentEvent",
+            "created",
+            "author",
+            issue,
+            "2017-05-01T08:00:00Z",
+        ));
+        assert_eq!(1, first_run.open_issue_count());
+        let opened_at = Utc.ymd(2017, 5, 1).and_hms(7, 0, 0);
+        assert_eq!(1, first_run.stats(155, opened_at).opened);
+        assert_eq!(0, first_run.stats(155, opened_at).responded);
+        first_run.drain();
+
+        let mut second_run = IssueResponsiveness::new(&obfuscation)
+            .with_open_issues(&first_run.open_issues_json())
+            .unwrap();
+        second_run.observe(&issue_event(
+            "IssueCommentEvent",
+            "created",
+            "maintainer",
+            issue,
+            "2017-05-01T09:00:00Z",
+        ));
+        // answered, so there's nothing left to carry over
+        assert_eq!(0, second_run.open_issue_count());
+        // the comment count shows it was answered before, so it's not a first response again
+        let third_comment = issue.replace("\"comments\": 1", "\"comments\": 3");
+        second_run.observe(&issue_event(
+            "IssueCommentEvent",
+            "created",
+            "other",
+            &third_comment,
+            "2017-05-01T10:00:00Z",
+        ));
+        let closed = r#"{"number": 3, "user": {"id": 1, "login": "author"}, "state": "closed",
+            "created_at": "2017-05-01T07:00:00Z", "closed_at": "2017-05-03T07:00:00Z"}"#;
+        second_run.observe(&issue_event(
+            "IssuesEvent",
+            "closed",
+            "maintainer",
+            closed,
+            "2017-05-03T07:00:00Z",
+        ));
+
+        let stats = second_run.stats(155, opened_at);
+        assert_eq!(
+            (1, 2 * 60 * 60),
+            (stats.responded, stats.seconds_to_first_response)
+        );
+        assert_eq!(
+            (1, 2 * 24 * 60 * 60),
+            (stats.closed, stats.seconds_to_close)
+        );
+        assert_eq!(0, second_run.open_issue_count());
+
+        let expected = "INSERT INTO issue_responsiveness (repo_id, month, opened, responded, seconds_to_first_response, closed, seconds_to_close) VALUES \
+            (155, '2017-05-01 00:00:00 UTC', 0, 1, 7200, 1, 172800) \
+            ON CONFLICT (repo_id, month) DO UPDATE SET \
+            opened = issue_responsiveness.opened + EXCLUDED.opened, \
+            responded = issue_responsiveness.responded + EXCLUDED.responded, \
+            seconds_to_first_response = issue_responsiveness.seconds_to_first_response + EXCLUDED.seconds_to_first_response, \
+            closed = issue_responsiveness.closed + EXCLUDED.closed, \
+            seconds_to_close = issue_responsiveness.seconds_to_close + EXCLUDED.seconds_to_close;";
+        assert_eq!(expected, second_run.drain());
+    }
+
+    // Closing a reopened issue again doesn't count as another close
+    #[test]
+    fn reopened_issues_close_once() {
+        let open = r#"{"number": 3, "user": {"id": 1, "login": "author"}, "state": "open", "comments": 0,
+            "created_at": "2017-05-01T07:00:00Z"}"#;
+        let closed = r#"{"number": 3, "user": {"id": 1, "login": "author"}, "state": "closed",
+            "created_at": "2017-05-01T07:00:00Z", "closed_at": "2017-05-02T07:00:00Z"}"#;
+        let obfuscation = Obfuscation::Plaintext;
+        let mut issues = IssueResponsiveness::new(&obfuscation);
+        issues.observe(&issue_event(
+            "IssuesEvent",
+            "opened",
+            "author",
+            open,
+            "2017-05-01T07:00:00Z",
+        ));
+        issues.observe(&issue_event(
+            "IssuesEvent",
+            "closed",
+            "maintainer",
+            closed,
+            "2017-05-02T07:00:00Z",
+        ));
+        issues.observe(&issue_event(
+            "IssuesEvent",
+            "reopened",
+            "author",
+            open,
+            "2017-05-03T07:00:00Z",
+        ));
+        assert_eq!(1, issues.open_issue_count());
+        issues.observe(&issue_event(
+            "IssuesEvent",
+            "closed",
+            "maintainer",
+            closed,
+            "2017-05-04T07:00:00Z",
+        ));
+        assert_eq!(0, issues.open_issue_count());
+
+        let stats = issues.stats(155, Utc.ymd(2017, 5, 1).and_hms(7, 0, 0));
+        assert_eq!((1, 1), (stats.opened, stats.closed));
+        assert_eq!(24 * 60 * 60, stats.seconds_to_close);
+    }
+
+    #[test]
+    fn old_unanswered_issues_expire() {
+        let issue = |number: i64, created_at: &str| {
+            format!(
+                r#"{{"number": {}, "user": {{"id": 1, "login": "author"}}, "state": "open", "comments": 0,
+                "created_at": "{}"}}"#,
+                number, created_at
+            )
+        };
+        let obfuscation = Obfuscation::Plaintext;
+        let mut issues = IssueResponsiveness::new(&obfuscation);
+        assert_eq!(0, issues.expire(Duration::days(30)));
+        issues.observe(&issue_event(
+            "IssuesEvent",
+            "opened",
+            "author",
+            &issue(3, "2017-01-01T07:00:00Z"),
+            "2017-01-01T07:00:00Z",
+        ));
+        issues.observe(&issue_event(
+            "IssuesEvent",
+            "opened",
+            "author",
+            &issue(4, "2017-05-01T07:00:00Z"),
+            "2017-05-01T07:00:00Z",
+        ));
+        assert_eq!(1, issues.expire(Duration::days(30)));
+        assert_eq!(1, issues.open_issue_count());
+        assert!(issues.open_issues_json().contains("\"number\":4"));
+    }
+}
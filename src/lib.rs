@@ -14,3 +14,81 @@ pub use crate::types::*;
 
 pub mod gha_sources;
 pub use crate::gha_sources::*;
+
+pub mod actor_profile;
+pub use crate::actor_profile::*;
+
+pub mod aggregate;
+pub use crate::aggregate::*;
+
+pub mod bots;
+pub use crate::bots::*;
+
+pub mod concentration;
+pub use crate::concentration::*;
+
+pub mod contributors;
+pub use crate::contributors::*;
+
+pub mod deny_list;
+pub use crate::deny_list::*;
+
+pub mod dedup;
+pub use crate::dedup::*;
+
+pub mod fork_graph;
+pub use crate::fork_graph::*;
+
+pub mod histogram;
+pub use crate::histogram::*;
+
+pub mod hll;
+pub use crate::hll::*;
+
+pub mod issues;
+pub use crate::issues::*;
+
+pub mod merge;
+pub use crate::merge::*;
+
+pub mod obfuscation;
+pub use crate::obfuscation::*;
+
+pub mod orgs;
+pub use crate::orgs::*;
+
+pub mod overlap;
+pub use crate::overlap::*;
+
+pub mod pull_requests;
+pub use crate::pull_requests::*;
+
+pub mod releases;
+pub use crate::releases::*;
+
+pub mod repo_history;
+pub use crate::repo_history::*;
+
+pub mod repo_lifecycle;
+pub use crate::repo_lifecycle::*;
+
+pub mod schema;
+pub use crate::schema::*;
+
+pub mod shard;
+pub use crate::shard::*;
+
+pub mod stars_forks;
+pub use crate::stars_forks::*;
+
+pub mod summary;
+pub use crate::summary::*;
+
+#[cfg(test)]
+pub(crate) mod test_events;
+
+pub mod time_bucket;
+pub use crate::time_bucket::*;
+
+pub mod top_k;
+pub use crate::top_k::*;
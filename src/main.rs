@@ -9,29 +9,35 @@ extern crate rusoto_core;
 extern crate rusoto_s3;
 extern crate serde;
 extern crate serde_json;
-extern crate sha1;
 #[macro_use]
 extern crate log;
 extern crate crossbeam_channel;
 
-use crossbeam_channel::bounded;
+use crossbeam_channel::{bounded, Sender};
+use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use rayon::prelude::*;
-use std::collections::BTreeMap;
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
+use std::fs;
 use std::io::prelude::*;
+use std::process;
 use std::str::FromStr;
+use std::sync::Mutex;
 use std::thread;
 use std::time::Instant;
 
-use rusoto_core::Region;
-use rusoto_s3::{DeleteObjectRequest, PutObjectRequest, S3Client, StreamingBody, S3};
+use futures::{Future, Stream};
+use rusoto_core::{Region, RusotoError};
+use rusoto_s3::{
+    DeleteObjectRequest, GetObjectError, GetObjectRequest, ListObjectsV2Request, PutObjectRequest,
+    S3Client, StreamingBody, S3,
+};
 use rusty_von_humboldt::*;
 
-const OBFUSCATE_COMMITTER_IDS: bool = true;
-
 lazy_static! {
     /// MODE contains what mode to do: committer count or repo mappings as well as if it should
     /// upload results to s3 or not (dry run).
@@ -54,6 +60,108 @@ lazy_static! {
                 Err(_) => false,
             }
         },
+        repo_name_history: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "repo_name_history",
+                Err(_) => false,
+            }
+        },
+        stars_forks: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "stars_forks",
+                Err(_) => false,
+            }
+        },
+        fork_graph: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "fork_graph",
+                Err(_) => false,
+            }
+        },
+        pr_lifecycle: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "pr_lifecycle",
+                Err(_) => false,
+            }
+        },
+        issue_response: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "issue_response",
+                Err(_) => false,
+            }
+        },
+        monthly_contributors: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "monthly_contributors",
+                Err(_) => false,
+            }
+        },
+        concentration: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "concentration",
+                Err(_) => false,
+            }
+        },
+        event_histogram: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "event_histogram",
+                Err(_) => false,
+            }
+        },
+        actor_profile: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "actor_profile",
+                Err(_) => false,
+            }
+        },
+        org_rollup: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "org_rollup",
+                Err(_) => false,
+            }
+        },
+        repo_lifecycle: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "repo_lifecycle",
+                Err(_) => false,
+            }
+        },
+        releases: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "releases",
+                Err(_) => false,
+            }
+        },
+        contributor_overlap: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "contributor_overlap",
+                Err(_) => false,
+            }
+        },
+        top_k: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "top_k",
+                Err(_) => false,
+            }
+        },
+        committer_sketches: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "committer_sketches",
+                Err(_) => false,
+            }
+        },
+        merge: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "merge",
+                Err(_) => false,
+            }
+        },
+        erasure_report: {
+            match env::var("MODE"){
+                Ok(mode) => mode == "erasure_report",
+                Err(_) => false,
+            }
+        },
         dry_run: {
             match env::var("DRYRUN"){
                 Ok(dryrun) => match bool::from_str(&dryrun) {
@@ -63,6 +171,18 @@ lazy_static! {
                 Err(_) => false,
             }
         },
+        schema_drift: {
+            match env::var("SCHEMADRIFT"){
+                Ok(schema_drift) => bool::from_str(&schema_drift).unwrap_or(false),
+                Err(_) => false,
+            }
+        },
+        dedup: {
+            match env::var("DEDUP"){
+                Ok(dedup) => bool::from_str(&dedup).unwrap_or(false),
+                Err(_) => false,
+            }
+        },
     };
 }
 
@@ -71,7 +191,34 @@ fn main() {
     environment_check();
     println!("Environment Check is complete.");
     let now = Instant::now();
-    sinker();
+    if MODE.erasure_report {
+        find_denied_in_outputs();
+        let summary = write_run_summary();
+        println!("This is Rusty von Humboldt, heading home.");
+        // Every file has to be checked for the report to be any use for an erasure request.
+        if summary.failure_count() > 0 {
+            error!(
+                "Couldn't check {} files, the erasure report is incomplete.",
+                summary.failure_count()
+            );
+            process::exit(1);
+        }
+        return;
+    }
+    if MODE.merge {
+        merge_shard_outputs();
+        println!("This is Rusty von Humboldt, heading home.");
+        return;
+    }
+    if MODE.top_k && *TOP_K_EXACT {
+        do_top_k_exact();
+    } else {
+        // A receiving thread that didn't finish is in the run summary, so MAXFAILURES covers it.
+        if sinker(do_mode_work).is_none() {
+            error!("Output for this run is incomplete.");
+        }
+    }
+    let summary = write_run_summary();
 
     let hours_to_process = env::var("GHAHOURS")
         .expect("Need GHAHOURS set to number of hours (files) to process")
@@ -84,6 +231,287 @@ fn main() {
         now.elapsed().as_secs()
     );
     println!("This is Rusty von Humboldt, heading home.");
+
+    if summary.exceeds(*MAX_FAILURES) {
+        error!(
+            "Lost {} files this run, more than MAXFAILURES of {} allows.",
+            summary.failure_count(),
+            *MAX_FAILURES
+        );
+        process::exit(1);
+    }
+}
+
+/// Put the run summary next to the output so we know which files didn't make it.
+fn write_run_summary() -> RunSummary {
+    let summary = RUN_SUMMARY
+        .lock()
+        .expect("run summary lock poisoned")
+        .clone();
+    let dest_bucket = env::var("DESTBUCKET").expect("Need DESTBUCKET set to bucket name");
+    let file_name = format!(
+        "{}/run_summary_{}.json",
+        output_dir(),
+        chrono::Utc::now().format("%Y%m%d%H%M%S")
+    );
+    info!(
+        "Skipped {} source files and failed {} uploads.",
+        summary.skipped_files.len(),
+        summary.failed_uploads.len()
+    );
+    if MODE.dedup {
+        info!("Dropped {} duplicate events.", summary.duplicates_dropped);
+    }
+    upload_to_s3(&dest_bucket, &file_name, summary.to_json().into_bytes());
+    write_quarantine(&dest_bucket);
+    if MODE.schema_drift {
+        write_schema_report(&dest_bucket);
+    }
+
+    // If the summary itself didn't upload that's recorded too, so hand back the latest.
+    RUN_SUMMARY
+        .lock()
+        .expect("run summary lock poisoned")
+        .clone()
+}
+
+/// Lines we couldn't parse go next to the output as gzipped JSON lines, one object per line.
+fn write_quarantine(dest_bucket: &str) {
+    let quarantined = QUARANTINE.lock().expect("quarantine lock poisoned");
+    if quarantined.is_empty() {
+        return;
+    }
+    info!("Quarantined {} lines we couldn't parse.", quarantined.len());
+    let contents = quarantined
+        .iter()
+        .map(|line| serde_json::to_string(line).expect("Couldn't serialize quarantined line"))
+        .collect::<Vec<String>>()
+        .join("\n");
+    let file_name = format!(
+        "{}/quarantine_{}.json.gz",
+        output_dir(),
+        chrono::Utc::now().format("%Y%m%d%H%M%S")
+    );
+    compress_and_upload(dest_bucket, &file_name, contents.as_bytes());
+}
+
+/// What fields GHA sent us per event type per month, compared to what we deserialize.
+fn write_schema_report(dest_bucket: &str) {
+    let report = SCHEMA.lock().expect("schema lock poisoned").report();
+    for field in report.disappeared.iter().filter(|field| field.known) {
+        warn!(
+            "{} stopped having {} in {}, last seen in {}.",
+            field.event_type, field.field, field.missing_from, field.last_seen
+        );
+    }
+    let file_name = format!(
+        "{}/schema_drift_{}.json",
+        output_dir(),
+        chrono::Utc::now().format("%Y%m%d%H%M%S")
+    );
+    upload_to_s3(dest_bucket, &file_name, report.to_json().into_bytes());
+}
+
+/// Look through everything we've uploaded for anything on the deny list, so we know which
+/// files from past runs need to be redone or deleted. The report is uploaded as JSON.
+fn find_denied_in_outputs() {
+    let dest_bucket = env::var("DESTBUCKET").expect("Need DESTBUCKET set to bucket name");
+    if DENY_LIST.is_empty() {
+        panic!("Please set DENYLIST to a deny list file to look for.");
+    }
+    let client = S3Client::new(Region::UsEast1);
+
+    let keys: Vec<(String, OutputFormat)> = list_output_keys(&client, &dest_bucket, "rvh2/")
+        .into_iter()
+        .filter_map(|key| OutputFormat::of_key(&key).map(|format| (key, format)))
+        .collect();
+    info!("Checking {} output files for denied entries.", keys.len());
+
+    let denied_outputs: Vec<DeniedOutput> = keys
+        .par_iter()
+        .filter_map(|(key, format)| {
+            let contents = match download_output(&client, &dest_bucket, key) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    error!("Couldn't check {}: {}", key, e);
+                    RUN_SUMMARY
+                        .lock()
+                        .expect("run summary lock poisoned")
+                        .skipped_file(key, &e);
+                    return None;
+                }
+            };
+            let matches = match DENY_LIST.find_in_output(&OBFUSCATION, *format, &contents) {
+                Ok(matches) => matches,
+                Err(e) => {
+                    error!("Couldn't check {}: {}", key, e);
+                    RUN_SUMMARY
+                        .lock()
+                        .expect("run summary lock poisoned")
+                        .skipped_file(key, &e);
+                    return None;
+                }
+            };
+            if matches.is_empty() {
+                None
+            } else {
+                Some(DeniedOutput {
+                    key: key.clone(),
+                    matches,
+                })
+            }
+        })
+        .collect();
+
+    for denied in &denied_outputs {
+        println!("{} contains {}", denied.key, denied.matches.join(" "));
+    }
+    let file_name = format!(
+        "rvh2/{}/{}.json",
+        generate_mode_string(),
+        chrono::Utc::now().format("%Y%m%d%H%M%S")
+    );
+    let report = serde_json::to_string_pretty(&denied_outputs).expect("Couldn't serialize report");
+    upload_to_s3(&dest_bucket, &file_name, report.into_bytes());
+}
+
+/// Every key in the destination bucket under `prefix`.
+fn list_output_keys(client: &S3Client, dest_bucket: &str, prefix: &str) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::new();
+    let mut continuation_token = None;
+    loop {
+        let list_obj_req = ListObjectsV2Request {
+            bucket: dest_bucket.to_string(),
+            prefix: Some(prefix.to_string()),
+            continuation_token: continuation_token.clone(),
+            ..Default::default()
+        };
+        let result = client
+            .list_objects_v2(list_obj_req)
+            .sync()
+            .expect("Couldn't list items in destination bucket");
+        for item in result.contents.unwrap_or_default() {
+            keys.push(item.key.expect("Key should exist for S3 item."));
+        }
+        continuation_token = result.next_continuation_token;
+        if continuation_token.is_none() {
+            break;
+        }
+    }
+    keys
+}
+
+/// Download and decompress one of our own gzipped outputs.
+fn download_output(client: &S3Client, dest_bucket: &str, key: &str) -> Result<String, String> {
+    let get_req = GetObjectRequest {
+        bucket: dest_bucket.to_string(),
+        key: key.to_string(),
+        ..Default::default()
+    };
+    let body = match client.get_object(get_req).sync() {
+        Ok(result) => result
+            .body
+            .expect("body should be preset")
+            .concat2()
+            .wait()
+            .map_err(|e| format!("Couldn't read body: {:?}", e))?
+            .to_vec(),
+        Err(e) => return Err(format!("{:?}", e)),
+    };
+    let mut contents = String::new();
+    GzDecoder::new(&body[..])
+        .read_to_string(&mut contents)
+        .map_err(|e| format!("Couldn't decompress: {:?}", e))?;
+    Ok(contents)
+}
+
+/// Every row from the SQL the shards wrote for `mode_string` in GHAYEAR. Bot lists are left out,
+/// they can be loaded from each shard as they are. Anything we can't download or parse stops the
+/// merge, since the result wouldn't match a single run without it, and so does anything other
+/// than output from every shard of a single split.
+fn shard_output_rows<T, F>(
+    client: &S3Client,
+    dest_bucket: &str,
+    mode_string: &str,
+    parse: F,
+) -> Vec<T>
+where
+    T: Send,
+    F: Fn(&str) -> Result<Vec<T>, String> + Sync,
+{
+    let prefix = format!("rvh2/{}/{}/shard_", mode_string, *YEAR);
+    let keys: Vec<String> = list_output_keys(client, dest_bucket, &prefix)
+        .into_iter()
+        .filter(|key| key.ends_with(".txt.gz") && !key.contains("/bots_"))
+        .collect();
+    let shards: BTreeSet<Shard> = keys
+        .iter()
+        .map(|key| {
+            let dir_name = key["rvh2/".len()..].split('/').nth(2).unwrap_or_default();
+            Shard::from_dir_name(dir_name)
+                .unwrap_or_else(|e| panic!("Couldn't merge {}: {}", key, e))
+        })
+        .collect();
+    let shard_count =
+        whole_split(&shards).unwrap_or_else(|e| panic!("Couldn't merge {}: {}", prefix, e));
+    info!(
+        "Merging {} files from {} shards under {}.",
+        keys.len(),
+        shard_count,
+        prefix
+    );
+    keys.par_iter()
+        .flat_map(|key| {
+            download_output(client, dest_bucket, key)
+                .and_then(|contents| parse(&contents))
+                .unwrap_or_else(|e| panic!("Couldn't merge {}: {}", key, e))
+        })
+        .collect()
+}
+
+/// Combine the committer counts and repo mappings the shards wrote for GHAYEAR into one
+/// deduplicated output, where a single run over every hour would have put it.
+fn merge_shard_outputs() {
+    let dest_bucket = env::var("DESTBUCKET").expect("Need DESTBUCKET set to bucket name");
+    let client = S3Client::new(Region::UsEast1);
+
+    let committers = merge_committer_rows(shard_output_rows(
+        &client,
+        &dest_bucket,
+        "committers",
+        parse_committer_rows,
+    ))
+    .unwrap_or_else(|e| panic!("{}", e));
+    info!("Merged to {} committer rows.", committers.len());
+    for (index, chunk) in committers.chunks(1_000_000).enumerate() {
+        let columns = match chunk[0].is_bot {
+            Some(_) => "(repo_id, actor_name, is_bot)",
+            None => "(repo_id, actor_name)",
+        };
+        let values: Vec<String> = chunk.iter().map(CommitterRow::as_sql_values).collect();
+        let file_name = format!("rvh2/committers/{}/{:02}.txt.gz", *YEAR, index + 1);
+        compress_and_upload(
+            &dest_bucket,
+            &file_name,
+            group_committer_values_sql_insert(&values, columns).as_bytes(),
+        );
+    }
+
+    let repo_mappings = latest_repo_names(shard_output_rows(
+        &client,
+        &dest_bucket,
+        "repomapping",
+        parse_repo_mapping_rows,
+    ));
+    info!("Merged to {} repo mappings.", repo_mappings.len());
+    for (index, chunk) in repo_mappings.chunks(1_000_000).enumerate() {
+        let file_name = format!("rvh2/repomapping/{}/01_{:02}.txt.gz", *YEAR, index + 1);
+        compress_and_upload(
+            &dest_bucket,
+            &file_name,
+            group_repo_id_sql_insert(chunk).as_bytes(),
+        );
+    }
 }
 
 /// Using channels to synchronize between sending threads and receiving thread.
@@ -93,21 +521,25 @@ fn main() {
 /// repository ID mapping is controlled by the MODE lazy static.
 ///
 /// Sending threads (two threads) take the to-process file list and downloads, deserializes and sends
-/// to the channel.
-fn sinker() {
+/// to the channel. The receiving thread's result is handed back, or None if it panicked.
+fn sinker<T, F>(worker: F) -> Option<T>
+where
+    T: Send + 'static,
+    F: FnOnce(crossbeam_channel::Receiver<EventWorkItem>, String) -> T + Send + 'static,
+{
     let dest_bucket = env::var("DESTBUCKET").expect("Need DESTBUCKET set to bucket name");
     // take the receive channel for file locations
     let mut file_list = construct_list_of_ingest_files();
+    sort_chronologically(&mut file_list);
+    check_whole_buckets(&file_list);
+    if let Some(shard) = *SHARD {
+        file_list = shard.select(file_list);
+        info!("Shard {} has {} files to download.", shard, file_list.len());
+    }
     let (send, recv) = bounded(10_000_000);
 
     // The receiving thread that accepts Events and converts them to the type needed.
-    let thread = thread::spawn(move || {
-        if MODE.committer_count {
-            do_work_son(recv, dest_bucket)
-        } else {
-            do_repo_work_son(recv, dest_bucket)
-        }
-    });
+    let thread = thread::spawn(move || worker(recv, dest_bucket));
 
     // send things all threaded like
     let send_a = send.clone();
@@ -123,84 +555,27 @@ fn sinker() {
     let pb = m.add(ProgressBar::new(file_list.len() as u64));
     pb.set_style(sty.clone());
 
-    let send_thread_a = thread::spawn(move || {
-        let client = S3Client::new(Region::UsEast1);
-        let mut c = 0;
-        pb.inc(0);
-        for files_to_fetch in file_list.chunks(2) {
-            debug!("Fetched {} files out of {}", c, file_list.len());
-            let event_subset = if MODE.committer_count {
-                get_event_subset_committers(&files_to_fetch, &client)
-            } else {
-                get_event_subset(&files_to_fetch, &client)
-            };
-            for event in event_subset {
-                let event_item = EventWorkItem {
-                    event,
-                    no_more_work: false,
-                };
-                if send_a.is_full() {
-                    debug!("send_a is full with length of {}", send_a.len());
-                }
-                // Should we retry on send failure? Failures probably only happen if
-                // the other side hangs up or something else we can't do much about.
-                match send_a.send(event_item) {
-                    Ok(_) => (),
-                    Err(e) => info!("Should have sent event, got error {}", e),
-                }
-            }
-            c += files_to_fetch.len();
-            pb.inc(files_to_fetch.len() as u64);
-        }
-        pb.finish_with_message("files downloaded");
-        debug!("Fetched all {} files.", file_list.len());
-    });
+    let send_thread_a = thread::spawn(move || send_events(&file_list, &send_a, &pb));
 
     let pb = m.add(ProgressBar::new(second_file_list.len() as u64));
     pb.set_style(sty.clone());
-    let send_thread_b = thread::spawn(move || {
-        let client = S3Client::new(Region::UsEast1);
-        let mut c = 0;
-        pb.inc(0);
-        for files_to_fetch in second_file_list.chunks(2) {
-            debug!("Fetched {} files out of {}", c, second_file_list.len());
-            let event_subset = if MODE.committer_count {
-                get_event_subset_committers(&files_to_fetch, &client)
-            } else {
-                get_event_subset(&files_to_fetch, &client)
-            };
-            for event in event_subset {
-                let event_item = EventWorkItem {
-                    event,
-                    no_more_work: false,
-                };
-                if send_b.is_full() {
-                    debug!("send_b is full with length of {}", send_b.len());
-                }
-                // Should we retry on send failure? Failures probably only happen if
-                // the other side hangs up or something else we can't do much about.
-                match send_b.send(event_item) {
-                    Ok(_) => (),
-                    Err(e) => info!("Should have sent event, got error {}", e),
-                }
-            }
-            c += files_to_fetch.len();
-            pb.inc(files_to_fetch.len() as u64);
-        }
-        pb.finish_with_message("files downloaded");
-        debug!("Fetched all {} files.", second_file_list.len());
-    });
+    let send_thread_b = thread::spawn(move || send_events(&second_file_list, &send_b, &pb));
     #[cfg(feature = "show_progress_bar")]
     m.join_and_clear().unwrap();
 
     // These join calls will block until the sending threads have completed all their work.
-    match send_thread_a.join() {
-        Ok(_) => info!("Thread all wrapped up."),
-        Err(e) => warn!("Thread didn't want to quit: {:?}", e),
-    }
-    match send_thread_b.join() {
-        Ok(_) => info!("Thread all wrapped up."),
-        Err(e) => warn!("Thread didn't want to quit: {:?}", e),
+    // A thread that panicked lost the rest of its files, so the run is missing data.
+    for (name, send_thread) in [
+        ("sending", send_thread_a),
+        ("second sending", send_thread_b),
+    ] {
+        match send_thread.join() {
+            Ok(_) => info!("Thread all wrapped up."),
+            Err(e) => {
+                error!("The {} thread didn't finish: {:?}", name, e);
+                record_failed_thread(name, &*e);
+            }
+        }
     }
 
     debug!("We're done sending items.");
@@ -217,11 +592,193 @@ fn sinker() {
     }
 
     // Wait for the worker thread to wrap up.
-    match thread.join() {
-        Ok(_) => info!("Thread all wrapped up."),
-        Err(e) => warn!("Thread didn't want to quit: {:?}", e),
-    }
+    let result = match thread.join() {
+        Ok(result) => {
+            info!("Thread all wrapped up.");
+            Some(result)
+        }
+        Err(e) => {
+            error!("The receiving thread didn't finish: {:?}", e);
+            record_failed_thread("receiving", &*e);
+            None
+        }
+    };
     info!("all wrapped up.");
+    result
+}
+
+/// Put a thread that panicked in the run summary, with its panic message if it had one.
+fn record_failed_thread(name: &str, panic: &(dyn Any + Send)) {
+    let reason = match panic.downcast_ref::<String>() {
+        Some(message) => message.clone(),
+        None => match panic.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => "panicked".to_string(),
+        },
+    };
+    RUN_SUMMARY
+        .lock()
+        .expect("run summary lock poisoned")
+        .failed_thread(name, &reason);
+}
+
+/// Modes that replace a bucket's rows with what the run saw need the run to cover whole buckets,
+/// or a bucket cut off at either end would overwrite the full one an earlier run wrote.
+fn check_whole_buckets(file_list: &[String]) {
+    let bucket = if MODE.org_rollup {
+        TimeBucket::Month
+    } else if MODE.top_k {
+        *TIME_BUCKET
+    } else {
+        return;
+    };
+    if let Some((start, end)) = covered_hours(file_list) {
+        if !bucket.whole_buckets(start, end) {
+            panic!(
+                "MODE {} needs whole {}s of hours but the files go from {} up to {}. Please set GHAHOURS so the run ends on a {} boundary.",
+                generate_mode_string(),
+                bucket.name(),
+                start,
+                end,
+                bucket.name()
+            );
+        }
+    }
+}
+
+/// The receiving thread's work for the MODE we're in.
+fn do_mode_work(recv: crossbeam_channel::Receiver<EventWorkItem>, dest_bucket: String) {
+    if MODE.committer_count {
+        do_work_son(recv, dest_bucket)
+    } else if MODE.repo_name_history {
+        do_aggregate_work_son(recv, dest_bucket, RepoNameHistory::default(), 1_000_000);
+    } else if MODE.stars_forks {
+        do_aggregate_work_son(recv, dest_bucket, StarsForks::new(*TIME_BUCKET), 1_000_000);
+    } else if MODE.fork_graph {
+        do_aggregate_work_son(recv, dest_bucket, ForkGraph::new(&OBFUSCATION), 1_000_000);
+    } else if MODE.pr_lifecycle {
+        do_aggregate_work_son(
+            recv,
+            dest_bucket,
+            PullRequestLifecycles::new(&OBFUSCATION),
+            1_000_000,
+        );
+    } else if MODE.issue_response {
+        let issues = load_open_issues(&dest_bucket);
+        let mut issues = do_aggregate_work_son(recv, dest_bucket.clone(), issues, 1_000_000);
+        let expired = issues.expire(chrono::Duration::days(*ISSUE_MAX_AGE_DAYS));
+        info!(
+            "Let go of {} issues unanswered for over {} days.",
+            expired, *ISSUE_MAX_AGE_DAYS
+        );
+        save_open_issues(&dest_bucket, &issues);
+    } else if MODE.monthly_contributors {
+        do_aggregate_work_son(
+            recv,
+            dest_bucket,
+            MonthlyContributors::new(&OBFUSCATION),
+            1_000_000,
+        );
+    } else if MODE.concentration {
+        do_aggregate_work_son(
+            recv,
+            dest_bucket,
+            ContributorConcentration::new(*WINDOW_DAYS),
+            1_000_000,
+        );
+    } else if MODE.event_histogram {
+        do_aggregate_work_son(recv, dest_bucket, EventTypeHistogram::default(), 1_000_000);
+    } else if MODE.actor_profile {
+        do_aggregate_work_son(
+            recv,
+            dest_bucket,
+            ActorProfiles::new(&OBFUSCATION),
+            1_000_000,
+        );
+    } else if MODE.org_rollup {
+        do_aggregate_work_son(recv, dest_bucket, OrgRollups::default(), 1_000_000);
+    } else if MODE.repo_lifecycle {
+        do_aggregate_work_son(recv, dest_bucket, RepoLifecycles::default(), 1_000_000);
+    } else if MODE.releases {
+        do_aggregate_work_son(recv, dest_bucket, Releases::new(&OBFUSCATION), 1_000_000);
+    } else if MODE.contributor_overlap {
+        do_aggregate_work_son(
+            recv,
+            dest_bucket,
+            ContributorOverlap::new(*WINDOW_DAYS, *OVERLAP_MAX_REPOS),
+            1_000_000,
+        );
+    } else if MODE.top_k {
+        let top_k = TopK::new(*TOP_K_METRIC, *TOP_K, *TOP_K_CAPACITY, *TIME_BUCKET);
+        do_aggregate_work_son(recv, dest_bucket, top_k, 1_000_000);
+    } else if MODE.committer_sketches {
+        // 50,000 precision 12 sketches are about 200 MB
+        let sketches = CommitterSketches::new(&OBFUSCATION, *TIME_BUCKET, *HLL_PRECISION);
+        do_aggregate_work_son(recv, dest_bucket, sketches, 50_000);
+    } else {
+        do_repo_work_son(recv, dest_bucket)
+    }
+}
+
+/// Top-K with an exact second pass over the same hours: the first pass finds the candidates
+/// with its sketches and the second counts just those exactly.
+fn do_top_k_exact() {
+    let first_pass = sinker(|recv, _| {
+        let mut top_k = TopK::new(*TOP_K_METRIC, *TOP_K, *TOP_K_CAPACITY, *TIME_BUCKET);
+        for_each_event(recv, |event| top_k.observe(event));
+        top_k
+    })
+    .expect("First top-K pass didn't finish");
+    let second_pass = first_pass.exact_second_pass();
+    sinker(move |recv, dest_bucket| {
+        do_aggregate_work_son(recv, dest_bucket, second_pass, 1_000_000);
+    });
+}
+
+/// Download, parse and send the events from each file to the receiving thread.
+///
+/// Event id deduplication happens here, per sending thread. The file list is sorted by hour before
+/// it's split, so the rolling window only ever sees hours in order. A duplicate split across the
+/// two halves of the file list won't be caught.
+fn send_events(file_list: &[String], send: &Sender<EventWorkItem>, pb: &ProgressBar) {
+    let client = S3Client::new(Region::UsEast1);
+    let mut deduper = EventDeduper::new(*DEDUP_WINDOW_HOURS);
+    let mut c = 0;
+    pb.inc(0);
+    for files_to_fetch in file_list.chunks(2) {
+        debug!("Fetched {} files out of {}", c, file_list.len());
+        let event_subset = if MODE.committer_count {
+            get_event_subset_committers(files_to_fetch, &client)
+        } else {
+            get_event_subset(files_to_fetch, &client)
+        };
+        for event in event_subset {
+            if MODE.dedup && !deduper.is_new(&event) {
+                continue;
+            }
+            let event_item = EventWorkItem {
+                event,
+                no_more_work: false,
+            };
+            if send.is_full() {
+                debug!("send is full with length of {}", send.len());
+            }
+            // Should we retry on send failure? Failures probably only happen if
+            // the other side hangs up or something else we can't do much about.
+            match send.send(event_item) {
+                Ok(_) => (),
+                Err(e) => info!("Should have sent event, got error {}", e),
+            }
+        }
+        c += files_to_fetch.len();
+        pb.inc(files_to_fetch.len() as u64);
+    }
+    pb.finish_with_message("files downloaded");
+    debug!("Fetched all {} files.", file_list.len());
+    RUN_SUMMARY
+        .lock()
+        .expect("run summary lock poisoned")
+        .duplicates_dropped += deduper.duplicates;
 }
 
 // dudupe RepoIdToName: if repo_id and repo_name are the same we can ditch one
@@ -253,8 +810,7 @@ fn do_repo_work_son(recv: crossbeam_channel::Receiver<EventWorkItem>, dest_bucke
             };
             if repo_mappings.len() % 2_000_000 == 0 {
                 debug!("Repo mapping size: {}", repo_mappings.len());
-                repo_mappings.sort();
-                repo_mappings.dedup_by(|a, b| a.repo_id == b.repo_id && a.repo_name == b.repo_name);
+                dedup_keeping_latest(&mut repo_mappings);
             }
             if item.no_more_work {
                 wrap_things_up = true;
@@ -269,8 +825,7 @@ fn do_repo_work_son(recv: crossbeam_channel::Receiver<EventWorkItem>, dest_bucke
         }
 
         let old_size = repo_mappings.len();
-        repo_mappings.sort();
-        repo_mappings.dedup_by(|a, b| a.repo_id == b.repo_id && a.repo_name == b.repo_name);
+        dedup_keeping_latest(&mut repo_mappings);
         debug!(
             "{:?}: We shrunk the repo events from {} to {}",
             thread::current().id(),
@@ -283,62 +838,171 @@ fn do_repo_work_son(recv: crossbeam_channel::Receiver<EventWorkItem>, dest_bucke
         repo_mappings.chunks(1_000_000).for_each(|chunk| {
             sql_bytes = group_repo_id_sql_insert(chunk).as_bytes().to_vec();
 
-            let file_name = format!(
-                "rvh2/{}/{}/{:02}_{:02}.txt.gz",
-                generate_mode_string(),
-                *YEAR,
-                index,
-                inner_index
-            );
+            let file_name = format!("{}/{:02}_{:02}.txt.gz", output_dir(), index, inner_index);
             inner_index += 1;
-            info!("compressing and uploading to s3");
-
-            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-            encoder.write_all(&sql_bytes).expect("encoding failed");
-            let compressed_results = encoder.finish().expect("Couldn't compress file, sad.");
-            info!("Compression done.");
-
-            // Since we're running and uploading from one account and putting into another account's bucket,
-            // cross account access requires us to apply the ACL or we'd get a 403 when accessing
-            // the destination file from the destination account's S3 bucket.
-            // See https://aws.amazon.com/premiumsupport/knowledge-center/s3-bucket-owner-access/ .
-            let upload_request = PutObjectRequest {
-                bucket: dest_bucket.clone(),
-                key: file_name.to_owned(),
-                body: Some(StreamingBody::from(compressed_results)),
-                acl: Some("bucket-owner-full-control".to_string()),
-                ..Default::default()
-            };
+            compress_and_upload(&dest_bucket, &file_name, &sql_bytes);
+        })
+    }
+}
 
-            let key_copy = upload_request.key.clone();
-            let bucket_copy = upload_request.bucket.clone();
+/// Keep the latest timestamp for each repo ID and name, which is what the upsert compares.
+fn dedup_keeping_latest(repo_mappings: &mut Vec<RepoIdToName>) {
+    repo_mappings.sort_by(|a, b| {
+        a.repo_id
+            .cmp(&b.repo_id)
+            .then_with(|| a.repo_name.cmp(&b.repo_name))
+            .then_with(|| b.event_timestamp.cmp(&a.event_timestamp))
+    });
+    repo_mappings.dedup_by(|a, b| a.repo_id == b.repo_id && a.repo_name == b.repo_name);
+}
 
-            if MODE.dry_run {
-                println!("Not uploading to S3, it's a dry run.  Would have uploaded to bucket {} and key {}.", upload_request.bucket, upload_request.key);
-            } else {
-                println!("Uploading to S3.");
-                // We create a new client every time since the underlying connection pool can
-                // deadlock if all the connections were closed by the receiving end (S3).
-                // This bypasses that issue by creating a new pool every time.
-                let client = S3Client::new(Region::UsEast1);
-                match client.put_object(upload_request).sync() {
-                    Ok(_) => println!(
-                        "uploaded {} to {}",
-                        key_copy, bucket_copy
-                    ),
-                    Err(e) => {
-                        error!("Couldn't upload results in file {}: {:?}", key_copy, e);
-                    }
-                }
+/// Call `f` with every event until the senders say there's no more.
+fn for_each_event<F: FnMut(&Event)>(recv: crossbeam_channel::Receiver<EventWorkItem>, mut f: F) {
+    loop {
+        let item: EventWorkItem = match recv.recv() {
+            Ok(i) => i,
+            Err(e) => {
+                error!("receiving error: {}. Moving on.", e);
+                continue;
             }
-        })
+        };
+        if item.no_more_work {
+            info!("wrapping thread up.");
+            break;
+        }
+        f(&item.event);
+    }
+}
+
+/// Feed events to the mode's aggregator, uploading what it has whenever it holds `flush_at`
+/// entries (if it can be flushed early) and once more when we're out of events. The aggregator
+/// is handed back for modes that keep something from one run to the next.
+fn do_aggregate_work_son<A: Aggregator>(
+    recv: crossbeam_channel::Receiver<EventWorkItem>,
+    dest_bucket: String,
+    mut aggregator: A,
+    flush_at: usize,
+) -> A {
+    let mut index = 0;
+    for_each_event(recv, |event| {
+        aggregator.observe(event);
+        if aggregator.can_flush_early() && aggregator.len() >= flush_at {
+            debug!("We got enough work to do!");
+            index += 1;
+            let extension = aggregator.file_extension();
+            upload_aggregate(&dest_bucket, index, extension, aggregator.drain());
+        }
+    });
+    if !aggregator.is_empty() {
+        index += 1;
+        let extension = aggregator.file_extension();
+        upload_aggregate(&dest_bucket, index, extension, aggregator.drain());
     }
+    aggregator
+}
+
+/// Where this run's output goes: `rvh2/<mode>/<year>`, in the shard's own directory with SHARD.
+fn output_dir() -> String {
+    // The erasure report covers every year.
+    if MODE.erasure_report {
+        return format!("rvh2/{}", generate_mode_string());
+    }
+    match *SHARD {
+        Some(shard) => format!(
+            "rvh2/{}/{}/{}",
+            generate_mode_string(),
+            *YEAR,
+            shard.dir_name()
+        ),
+        None => format!("rvh2/{}/{}", generate_mode_string(), *YEAR),
+    }
+}
+
+fn upload_aggregate(dest_bucket: &str, index: i32, extension: &str, output: String) {
+    let file_name = format!("{}/{:02}.{}.gz", output_dir(), index, extension);
+    compress_and_upload(dest_bucket, &file_name, output.as_bytes());
+}
+
+/// Where issue responsiveness keeps the issues still open at the end of a run over `year`.
+fn open_issues_key(year: i32) -> String {
+    format!(
+        "rvh2/{}/{}/open_issues.json.gz",
+        generate_mode_string(),
+        year
+    )
+}
+
+/// Issues left open by the previous run this year, or by the last run over the year before for
+/// the first run of a year, or none if there's neither.
+fn load_open_issues(dest_bucket: &str) -> IssueResponsiveness<'static> {
+    match fetch_open_issues(dest_bucket, *YEAR) {
+        Some(issues) => issues,
+        None => match fetch_open_issues(dest_bucket, *YEAR - 1) {
+            Some(issues) => issues,
+            None => {
+                info!("No open issues from a previous run, starting fresh.");
+                IssueResponsiveness::new(&OBFUSCATION)
+            }
+        },
+    }
+}
+
+/// The issues left open for `year`, or None if no run over it saved any.
+fn fetch_open_issues(dest_bucket: &str, year: i32) -> Option<IssueResponsiveness<'static>> {
+    let issues = IssueResponsiveness::new(&OBFUSCATION);
+    let get_req = GetObjectRequest {
+        bucket: dest_bucket.to_string(),
+        key: open_issues_key(year),
+        ..Default::default()
+    };
+    let client = S3Client::new(Region::UsEast1);
+    match client.get_object(get_req).sync() {
+        Ok(result) => {
+            let body = result
+                .body
+                .expect("body should be preset")
+                .concat2()
+                .wait()
+                .expect("Couldn't read open issues")
+                .to_vec();
+            let mut json = String::new();
+            GzDecoder::new(&body[..])
+                .read_to_string(&mut json)
+                .expect("Couldn't decompress open issues");
+            let issues = issues
+                .with_open_issues(&json)
+                .unwrap_or_else(|e| panic!("{}", e));
+            info!(
+                "Loaded {} open issues from {}.",
+                issues.open_issue_count(),
+                year
+            );
+            Some(issues)
+        }
+        Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => None,
+        // Carrying on would count every open issue's response and close as if we'd never seen it.
+        Err(e) => panic!(
+            "Couldn't load open issues from {}: {:?}",
+            open_issues_key(year),
+            e
+        ),
+    }
+}
+
+fn save_open_issues(dest_bucket: &str, issues: &IssueResponsiveness) {
+    info!("Saving {} open issues.", issues.open_issue_count());
+    compress_and_upload(
+        dest_bucket,
+        &open_issues_key(*YEAR),
+        issues.open_issues_json().as_bytes(),
+    );
 }
 
 /// Committer count
 fn do_work_son(recv: crossbeam_channel::Receiver<EventWorkItem>, dest_bucket: String) {
     let mut wrap_things_up = false;
     let mut commiter_events_bt: BTreeMap<CommitEvent, i64> = BTreeMap::new();
+    let mut bots = BotClassifier::new(known_bots(), *BOT_PUSHES_PER_HOUR, *BOT_REPOS_PER_DAY);
     let mut sql_collector: Vec<String> = Vec::new();
     let mut sql_bytes: Vec<u8> = Vec::new();
     let mut index = 0;
@@ -368,10 +1032,12 @@ fn do_work_son(recv: crossbeam_channel::Receiver<EventWorkItem>, dest_bucket: St
                 wrap_things_up = true;
                 break;
             } else {
-                // TODO: we can make this count commits of actor to repo.
-                commiter_events_bt
+                if *BOT_HANDLING != BotHandling::Include {
+                    bots.observe(&item.event);
+                }
+                *commiter_events_bt
                     .entry(item.event.as_commit_event())
-                    .or_insert(1);
+                    .or_insert(0) += item.event.commit_count();
             }
             // 20,000,000 was too high for 4 GB of RAM
             if commiter_events_bt.len() == 1_000_000 {
@@ -380,59 +1046,104 @@ fn do_work_son(recv: crossbeam_channel::Receiver<EventWorkItem>, dest_bucket: St
             }
         }
 
-        sql_bytes = group_committer_sql_insert_par(&commiter_events_bt, OBFUSCATE_COMMITTER_IDS)
+        if *BOT_HANDLING == BotHandling::Exclude {
+            commiter_events_bt.retain(|commit_event, _| !bots.is_bot(&commit_event.actor));
+        }
+        let tag_bots = match *BOT_HANDLING {
+            BotHandling::Tag => Some(&bots),
+            _ => None,
+        };
+        sql_bytes = group_committer_sql_insert_par(&commiter_events_bt, &OBFUSCATION, tag_bots)
             .as_bytes()
             .to_vec();
 
+        let file_name = format!("{}/{:02}.txt.gz", output_dir(), index);
+
+        // It'd be nice to fire this off to a thread:
+        compress_and_upload(&dest_bucket, &file_name, &sql_bytes);
+    }
+
+    // Accounts flagged partway through the run were counted, or tagged, as people before that.
+    // This list is the complete one, and lets them be cleaned up afterwards.
+    if !bots.flagged().is_empty() {
+        info!(
+            "Flagged {} accounts as bots by behavior.",
+            bots.flagged().len()
+        );
         let file_name = format!(
-            "rvh2/{}/{}/{:02}.txt.gz",
-            generate_mode_string(),
-            *YEAR,
-            index
+            "{}/bots_{}.txt.gz",
+            output_dir(),
+            chrono::Utc::now().format("%Y%m%d%H%M%S")
         );
+        compress_and_upload(
+            &dest_bucket,
+            &file_name,
+            group_bot_sql_insert(bots.flagged(), &OBFUSCATION).as_bytes(),
+        );
+    }
+}
 
-        // It'd be nice to fire this off to a thread:
-        info!("compressing and uploading to s3");
-
-        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(&sql_bytes).expect("encoding failed");
-        let compressed_results = encoder.finish().expect("Couldn't compress file, sad.");
-        info!("Compression done.");
-
-        // Since we're running and uploading from one account and putting into another account's bucket,
-        // cross account access requires us to apply the ACL or we'd get a 403 when accessing
-        // the destination file from the destination account's S3 bucket.
-        // See https://aws.amazon.com/premiumsupport/knowledge-center/s3-bucket-owner-access/ .
-        let upload_request = PutObjectRequest {
-            bucket: dest_bucket.clone(),
-            key: file_name.to_owned(),
-            body: Some(StreamingBody::from(compressed_results)),
-            acl: Some("bucket-owner-full-control".to_string()),
-            ..Default::default()
-        };
+/// Bots from BOTLIST, one login per line.
+fn known_bots() -> BTreeSet<String> {
+    match env::var("BOTLIST") {
+        Ok(path) => fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Couldn't read BOTLIST {}: {}", path, e))
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect(),
+        Err(_) => BTreeSet::new(),
+    }
+}
 
-        let key_copy = upload_request.key.clone();
-        let bucket_copy = upload_request.bucket.clone();
+/// Gzip the results and put them in the destination bucket.
+fn compress_and_upload(dest_bucket: &str, file_name: &str, contents: &[u8]) {
+    info!("compressing and uploading to s3");
 
-        {
-            if MODE.dry_run {
-                info!("Not uploading to S3, it's a dry run.  Would have uploaded to bucket {} and key {}.",
-                         upload_request.bucket,
-                         upload_request.key);
-                continue;
-            }
-            let client = S3Client::new(Region::UsEast1);
-            info!("Uploading to S3.");
-            // TODO: is this true with 0.41 or later versions of Rusoto?
-            // We create a new client every time since the underlying connection pool can
-            // deadlock if all the connections were closed by the receiving end (S3).
-            // This bypasses that issue by creating a new pool every time.
-            match client.put_object(upload_request).sync() {
-                Ok(_) => info!("uploaded {} to {}", key_copy, bucket_copy),
-                Err(e) => {
-                    error!("Couldn't upload results to file {}: {:?}", key_copy, e);
-                }
-            }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(contents).expect("encoding failed");
+    let compressed_results = encoder.finish().expect("Couldn't compress file, sad.");
+    info!("Compression done.");
+
+    upload_to_s3(dest_bucket, file_name, compressed_results);
+}
+
+/// Upload to the destination bucket unless it's a dry run. Failures are recorded in the run summary.
+fn upload_to_s3(dest_bucket: &str, file_name: &str, contents: Vec<u8>) {
+    if MODE.dry_run {
+        info!(
+            "Not uploading to S3, it's a dry run.  Would have uploaded to bucket {} and key {}.",
+            dest_bucket, file_name
+        );
+        return;
+    }
+
+    // Since we're running and uploading from one account and putting into another account's bucket,
+    // cross account access requires us to apply the ACL or we'd get a 403 when accessing
+    // the destination file from the destination account's S3 bucket.
+    // See https://aws.amazon.com/premiumsupport/knowledge-center/s3-bucket-owner-access/ .
+    let upload_request = PutObjectRequest {
+        bucket: dest_bucket.to_string(),
+        key: file_name.to_owned(),
+        body: Some(StreamingBody::from(contents)),
+        acl: Some("bucket-owner-full-control".to_string()),
+        ..Default::default()
+    };
+
+    info!("Uploading to S3.");
+    // TODO: is this true with 0.41 or later versions of Rusoto?
+    // We create a new client every time since the underlying connection pool can
+    // deadlock if all the connections were closed by the receiving end (S3).
+    // This bypasses that issue by creating a new pool every time.
+    let client = S3Client::new(Region::UsEast1);
+    match client.put_object(upload_request).sync() {
+        Ok(_) => info!("uploaded {} to {}", file_name, dest_bucket),
+        Err(e) => {
+            error!("Couldn't upload results to file {}: {:?}", file_name, e);
+            RUN_SUMMARY
+                .lock()
+                .expect("run summary lock poisoned")
+                .failed_upload(file_name, &format!("{:?}", e));
         }
     }
 }
@@ -441,22 +1152,105 @@ fn generate_mode_string() -> String {
     if MODE.committer_count {
         return "committers".to_string();
     }
+    if MODE.repo_name_history {
+        return "reponamehistory".to_string();
+    }
+    if MODE.stars_forks {
+        return "starsforks".to_string();
+    }
+    if MODE.fork_graph {
+        return "forkgraph".to_string();
+    }
+    if MODE.pr_lifecycle {
+        return "prlifecycle".to_string();
+    }
+    if MODE.issue_response {
+        return "issueresponse".to_string();
+    }
+    if MODE.monthly_contributors {
+        return "monthlycontributors".to_string();
+    }
+    if MODE.concentration {
+        return "concentration".to_string();
+    }
+    if MODE.event_histogram {
+        return "eventhistogram".to_string();
+    }
+    if MODE.actor_profile {
+        return "actorprofile".to_string();
+    }
+    if MODE.org_rollup {
+        return "orgrollup".to_string();
+    }
+    if MODE.repo_lifecycle {
+        return "repolifecycle".to_string();
+    }
+    if MODE.releases {
+        return "releases".to_string();
+    }
+    if MODE.contributor_overlap {
+        return "contributoroverlap".to_string();
+    }
+    if MODE.top_k {
+        return "topk".to_string();
+    }
+    if MODE.committer_sketches {
+        return "committersketches".to_string();
+    }
+    if MODE.merge {
+        return "merge".to_string();
+    }
+    if MODE.erasure_report {
+        return "erasurereport".to_string();
+    }
     "repomapping".to_string()
 }
 
 // check things like dryrun etc
 fn environment_check() {
     let d = env::var("DESTBUCKET").expect("Need DESTBUCKET set to bucket name");
-    let _ = env::var("GHABUCKET").expect("Need GHABUCKET set to bucket name");
-    let _ = env::var("GHAYEAR").expect("Need GHAYEAR set to year to process");
-    let _ = env::var("GHAHOURS")
-        .expect("Need GHAHOURS set to number of hours (files) to process")
-        .parse::<i64>()
-        .expect("Please set GHAHOURS to an integer value");
+    // The erasure report and merge only look at our own output.
+    if !MODE.erasure_report && !MODE.merge {
+        let _ = env::var("GHABUCKET").expect("Need GHABUCKET set to bucket name");
+        let _ = env::var("GHAYEAR").expect("Need GHAYEAR set to year to process");
+        let _ = env::var("GHAHOURS")
+            .expect("Need GHAHOURS set to number of hours (files) to process")
+            .parse::<i64>()
+            .expect("Please set GHAHOURS to an integer value");
+    }
     env_logger::init();
     info!("Mode is {:?}", *MODE);
-    if MODE.committer_count == MODE.repo_mapping {
-        panic!("Please set either commiter count mode or repo mapping mode.");
+    // Resolved here rather than on first use, so a missing key stops the run before any work
+    // and modes that never write actors don't need one.
+    if MODE.writes_actor_identities() {
+        let obfuscation: &Obfuscation = &OBFUSCATION;
+        info!("Actor obfuscation is {:?}", obfuscation);
+    }
+    info!("Bots are handled with {:?}", *BOT_HANDLING);
+    info!(
+        "Exiting non-zero if more than {} files are lost",
+        *MAX_FAILURES
+    );
+    info!(
+        "Deny list has {} logins, {} actor IDs and {} repo IDs",
+        DENY_LIST.logins.len(),
+        DENY_LIST.actor_ids.len(),
+        DENY_LIST.repo_ids.len()
+    );
+    if let Some(shard) = *SHARD {
+        if MODE.merge {
+            panic!(
+                "Please unset SHARD to merge, the merged output goes where a single run's would."
+            );
+        }
+        // Other modes' per-shard output would overwrite each other's rows or state when loaded.
+        if !MODE.committer_count && !MODE.repo_mapping {
+            panic!("SHARD only works with MODE committer_count or repo_mapping, the modes merge can combine.");
+        }
+        info!("Processing shard {} of the source files", shard);
+    }
+    if MODE.modes_selected() != 1 {
+        panic!("Please set MODE to committer_count, repo_mapping, repo_name_history, stars_forks, fork_graph, pr_lifecycle, issue_response, monthly_contributors, concentration, event_histogram, actor_profile, org_rollup, repo_lifecycle, releases, contributor_overlap, top_k, committer_sketches, merge or erasure_report.");
     }
 
     check_dest_bucket_write_access(&d);
@@ -490,62 +1284,74 @@ fn check_dest_bucket_write_access(bucket_name: &str) {
     }
 }
 
+/// Only the histogram reads pre-2015 files, no other mode has been checked against converted
+/// events. Elsewhere their lines fail to parse and end up in quarantine, as they always have.
+fn download_and_parse(file_name: &str, client: &S3Client) -> Result<ParsedFile<Event>, String> {
+    if MODE.event_histogram {
+        download_and_parse_any_file(file_name, client, MODE.schema_drift, &DENY_LIST)
+    } else {
+        download_and_parse_file(file_name, client, MODE.schema_drift, &DENY_LIST)
+    }
+}
+
 /// Get all events from the file specified on S3
 fn get_event_subset(chunk: &[String], client: &S3Client) -> Vec<Event> {
     chunk
         .par_iter()
-        .flat_map(
-            |file_name| match download_and_parse_file(file_name, &client) {
-                Ok(r) => r,
-                Err(e) => {
-                    info!(
-                        "Issue with file download/parse for file {}: {}",
-                        file_name, e
-                    );
-                    match download_and_parse_file(file_name, &client) {
-                        Ok(r) => r,
-                        Err(e) => {
-                            info!(
-                                "Second attempt to download file failed, skipping {} because: {}",
-                                file_name, e
-                            );
-                            Vec::new()
-                        }
+        .flat_map(|file_name| match download_and_parse(file_name, client) {
+            Ok(r) => record_parse_results(r),
+            Err(e) => {
+                info!(
+                    "Issue with file download/parse for file {}: {}",
+                    file_name, e
+                );
+                match download_and_parse(file_name, client) {
+                    Ok(r) => record_parse_results(r),
+                    Err(e) => {
+                        error!(
+                            "Second attempt to download file failed, skipping {} because: {}",
+                            file_name, e
+                        );
+                        RUN_SUMMARY
+                            .lock()
+                            .expect("run summary lock poisoned")
+                            .skipped_file(file_name, &e);
+                        Vec::new()
                     }
                 }
-            },
-        )
+            }
+        })
         .collect()
 }
 
+/// Keep the parse stats and quarantined lines for the end of the run, pass the events along.
+fn record_parse_results(mut parsed: ParsedFile<Event>) -> Vec<Event> {
+    parsed.cap_quarantine(*QUARANTINE_MAX_LINES);
+    if !parsed.quarantined.is_empty() {
+        QUARANTINE
+            .lock()
+            .expect("quarantine lock poisoned")
+            .extend(parsed.quarantined);
+    }
+    if !parsed.schema.is_empty() {
+        SCHEMA
+            .lock()
+            .expect("schema lock poisoned")
+            .merge(parsed.schema);
+    }
+    RUN_SUMMARY
+        .lock()
+        .expect("run summary lock poisoned")
+        .parsed_file(parsed.stats);
+    parsed.events
+}
+
 /// Get commit/PR events from the file specified on S3
 fn get_event_subset_committers(chunk: &[String], client: &S3Client) -> Vec<Event> {
-    let commit_events: Vec<Event> = chunk
-        .par_iter()
-        .flat_map(
-            |file_name| match download_and_parse_file(file_name, &client) {
-                Ok(r) => r,
-                Err(e) => {
-                    info!(
-                        "Issue with file download/parse for file {}: {}",
-                        file_name, e
-                    );
-                    match download_and_parse_file(file_name, &client) {
-                        Ok(r) => r,
-                        Err(e) => {
-                            info!(
-                                "Second attempt to download file failed, skipping {} because: {}",
-                                file_name, e
-                            );
-                            Vec::new()
-                        }
-                    }
-                }
-            },
-        )
-        .filter(|ref x| x.is_commit_event())
-        .collect();
-    commit_events
+    get_event_subset(chunk, client)
+        .into_iter()
+        .filter(|x| x.is_commit_event())
+        .collect()
 }
 
 /// Struct representing a completed item of work to upload to S3.
@@ -563,7 +1369,70 @@ struct WorkItem {
 struct Mode {
     committer_count: bool,
     repo_mapping: bool,
+    repo_name_history: bool,
+    stars_forks: bool,
+    fork_graph: bool,
+    pr_lifecycle: bool,
+    issue_response: bool,
+    monthly_contributors: bool,
+    concentration: bool,
+    event_histogram: bool,
+    actor_profile: bool,
+    org_rollup: bool,
+    repo_lifecycle: bool,
+    releases: bool,
+    contributor_overlap: bool,
+    top_k: bool,
+    committer_sketches: bool,
+    merge: bool,
+    erasure_report: bool,
     dry_run: bool,
+    schema_drift: bool,
+    dedup: bool,
+}
+
+impl Mode {
+    /// We can only do one thing per run.
+    fn modes_selected(&self) -> usize {
+        [
+            self.committer_count,
+            self.repo_mapping,
+            self.repo_name_history,
+            self.stars_forks,
+            self.fork_graph,
+            self.pr_lifecycle,
+            self.issue_response,
+            self.monthly_contributors,
+            self.concentration,
+            self.event_histogram,
+            self.actor_profile,
+            self.org_rollup,
+            self.repo_lifecycle,
+            self.releases,
+            self.contributor_overlap,
+            self.top_k,
+            self.committer_sketches,
+            self.merge,
+            self.erasure_report,
+        ]
+        .iter()
+        .filter(|selected| **selected)
+        .count()
+    }
+
+    /// Modes that write actor logins, or look for them in what was written, and so need
+    /// OBFUSCATION.
+    fn writes_actor_identities(&self) -> bool {
+        self.committer_count
+            || self.fork_graph
+            || self.pr_lifecycle
+            || self.issue_response
+            || self.monthly_contributors
+            || self.actor_profile
+            || self.releases
+            || self.committer_sketches
+            || self.erasure_report
+    }
 }
 
 /// Struct representing a file to download and parse.
@@ -591,6 +1460,201 @@ lazy_static! {
     };
 }
 
+lazy_static! {
+    /// Source files we skipped and uploads that failed, shared by the download and upload threads.
+    static ref RUN_SUMMARY: Mutex<RunSummary> = Mutex::new(RunSummary::default());
+
+    /// Lines from the source files serde rejected, written out at the end of the run.
+    static ref QUARANTINE: Mutex<Vec<QuarantinedLine>> = Mutex::new(Vec::new());
+
+    /// Fields seen per event type per month, only filled in with SCHEMADRIFT set.
+    static ref SCHEMA: Mutex<SchemaObservations> = Mutex::new(SchemaObservations::default());
+
+    /// How many lost files (skipped downloads plus failed uploads) we put up with before exiting non-zero.
+    static ref MAX_FAILURES: usize = {
+        match env::var("MAXFAILURES") {
+            Ok(max) => max
+                .parse::<usize>()
+                .expect("Please set MAXFAILURES to a non-negative integer value"),
+            Err(_) => 0,
+        }
+    };
+
+    /// How many lines we couldn't parse are kept per source file, QUARANTINEMAXLINES or 100.
+    static ref QUARANTINE_MAX_LINES: usize = {
+        match env::var("QUARANTINEMAXLINES") {
+            Ok(max) => max
+                .parse::<usize>()
+                .expect("Please set QUARANTINEMAXLINES to a non-negative integer value"),
+            Err(_) => 100,
+        }
+    };
+
+    /// How actor logins are written out: OBFUSCATION is hmac (the default), pseudonym or plaintext.
+    /// The keyed ones take their secret from the file at OBFUSCATIONKEYFILE or from OBFUSCATIONKEY.
+    static ref OBFUSCATION: Obfuscation = {
+        let method = env::var("OBFUSCATION").unwrap_or_else(|_| "hmac".to_string());
+        let key = match env::var("OBFUSCATIONKEYFILE") {
+            Ok(path) => {
+                let key = fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("Couldn't read OBFUSCATIONKEYFILE {}: {}", path, e));
+                Some(key.trim_end().as_bytes().to_vec())
+            }
+            Err(_) => env::var("OBFUSCATIONKEY")
+                .ok()
+                .map(|key| key.trim_end().as_bytes().to_vec()),
+        };
+        match Obfuscation::new(&method, key) {
+            Ok(obfuscation) => obfuscation,
+            Err(e) => panic!("{}. Set OBFUSCATIONKEYFILE or OBFUSCATIONKEY, or OBFUSCATION=plaintext.", e),
+        }
+    };
+
+    /// Logins, actor IDs and repo IDs to leave out, from the JSON file at DENYLIST.
+    static ref DENY_LIST: DenyList = {
+        match env::var("DENYLIST") {
+            Ok(path) => {
+                let json = fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("Couldn't read DENYLIST {}: {}", path, e));
+                DenyList::from_json(&json).unwrap_or_else(|e| panic!("{}", e))
+            }
+            Err(_) => DenyList::default(),
+        }
+    };
+
+    /// What to do with bots in committer counts: BOTS is include (the default), exclude or tag.
+    static ref BOT_HANDLING: BotHandling = {
+        match env::var("BOTS") {
+            Ok(handling) => handling.parse().unwrap_or_else(|e| panic!("{}", e)),
+            Err(_) => BotHandling::Include,
+        }
+    };
+
+    /// More pushes than this by one account in an hour and we call it a bot.
+    static ref BOT_PUSHES_PER_HOUR: u32 = {
+        match env::var("BOTPUSHESPERHOUR") {
+            Ok(pushes) => pushes
+                .parse::<u32>()
+                .expect("Please set BOTPUSHESPERHOUR to an integer value"),
+            Err(_) => 200,
+        }
+    };
+
+    /// Commits to more repos than this by one account in a day and we call it a bot.
+    static ref BOT_REPOS_PER_DAY: usize = {
+        match env::var("BOTREPOSPERDAY") {
+            Ok(repos) => repos
+                .parse::<usize>()
+                .expect("Please set BOTREPOSPERDAY to an integer value"),
+            Err(_) => 1000,
+        }
+    };
+
+    /// How many hours back from the newest event we remember event ids for when deduplicating.
+    static ref DEDUP_WINDOW_HOURS: i64 = {
+        match env::var("DEDUPHOURS") {
+            Ok(hours) => hours
+                .parse::<i64>()
+                .expect("Please set DEDUPHOURS to an integer value"),
+            Err(_) => 3,
+        }
+    };
+
+    /// How time series modes group events: TIMEBUCKET is hour, day (the default), week or month.
+    static ref TIME_BUCKET: TimeBucket = {
+        match env::var("TIMEBUCKET") {
+            Ok(bucket) => bucket.parse().unwrap_or_else(|e| panic!("{}", e)),
+            Err(_) => TimeBucket::Day,
+        }
+    };
+
+    /// How many days back from the newest event contributor concentration and overlap look at.
+    static ref WINDOW_DAYS: i64 = {
+        match env::var("WINDOWDAYS") {
+            Ok(days) => days
+                .parse::<i64>()
+                .expect("Please set WINDOWDAYS to an integer value"),
+            Err(_) => 90,
+        }
+    };
+
+    /// Unanswered issues older than this are dropped from the ones issue responsiveness carries over.
+    static ref ISSUE_MAX_AGE_DAYS: i64 = {
+        match env::var("ISSUEMAXAGEDAYS") {
+            Ok(days) => days
+                .parse::<i64>()
+                .expect("Please set ISSUEMAXAGEDAYS to an integer value"),
+            Err(_) => 365,
+        }
+    };
+
+    /// Committers to more repos than this in the window are left out of the overlap graph.
+    static ref OVERLAP_MAX_REPOS: usize = {
+        match env::var("OVERLAPMAXREPOS") {
+            Ok(repos) => repos
+                .parse::<usize>()
+                .expect("Please set OVERLAPMAXREPOS to an integer value"),
+            Err(_) => 100,
+        }
+    };
+
+    /// What top-K ranks repos by: TOPKMETRIC is stars (the default), committers, forks, merged_prs or pushes.
+    static ref TOP_K_METRIC: TopKMetric = {
+        match env::var("TOPKMETRIC") {
+            Ok(metric) => metric.parse().unwrap_or_else(|e| panic!("{}", e)),
+            Err(_) => TopKMetric::Stars,
+        }
+    };
+
+    /// How many repos top-K ranks per time bucket.
+    static ref TOP_K: usize = {
+        match env::var("TOPK") {
+            Ok(k) => k.parse::<usize>().expect("Please set TOPK to an integer value"),
+            Err(_) => 1000,
+        }
+    };
+
+    /// How many repos top-K's sketches track per time bucket, ten times TOPK unless set.
+    static ref TOP_K_CAPACITY: usize = {
+        match env::var("TOPKCAPACITY") {
+            Ok(capacity) => capacity
+                .parse::<usize>()
+                .expect("Please set TOPKCAPACITY to an integer value"),
+            Err(_) => *TOP_K * 10,
+        }
+    };
+
+    /// Which part of the source files this machine works on, from SHARD as i/N.
+    static ref SHARD: Option<Shard> = {
+        match env::var("SHARD") {
+            Ok(shard) => Some(shard.parse().unwrap_or_else(|e| panic!("{}", e))),
+            Err(_) => None,
+        }
+    };
+
+    /// Precision of committer sketches: each takes 2^HLLPRECISION bytes.
+    static ref HLL_PRECISION: u8 = {
+        match env::var("HLLPRECISION") {
+            Ok(precision) => precision
+                .parse::<u8>()
+                .ok()
+                .filter(|precision| {
+                    (HyperLogLog::MIN_PRECISION..=HyperLogLog::MAX_PRECISION).contains(precision)
+                })
+                .expect("Please set HLLPRECISION to an integer from 4 to 16"),
+            Err(_) => 12,
+        }
+    };
+
+    /// Go over the hours a second time to count top-K's candidates exactly.
+    static ref TOP_K_EXACT: bool = {
+        match env::var("TOPKEXACT") {
+            Ok(exact) => bool::from_str(&exact).unwrap_or(false),
+            Err(_) => false,
+        }
+    };
+}
+
 // if a repo ID shows up twice the collection we received has a duplicate in it
 fn dupes_in(repo_id_mappings: &[RepoIdToName]) -> bool {
     let mut repo_ids = repo_id_mappings
@@ -606,34 +1670,71 @@ fn dupes_in(repo_id_mappings: &[RepoIdToName]) -> bool {
     false
 }
 
+/// With `tag_bots` each row says if the committer is a bot.
 fn group_committer_sql_insert_par(
     committers: &BTreeMap<CommitEvent, i64>,
-    obfuscate: bool,
+    obfuscation: &Obfuscation,
+    tag_bots: Option<&BotClassifier>,
 ) -> String {
     // Get the repo id and actor names
     let a = committers
         .iter()
         .map(|commit_event| {
-            let actor_name = if obfuscate {
-                let mut sha_er = sha1::Sha1::new();
-                sha_er.update(commit_event.0.actor.as_bytes());
-                sha_er.digest().to_string()
-            } else {
-                commit_event.0.actor.clone()
-            };
-
-            format!("({}, '{}')", commit_event.0.repo_id, actor_name)
+            let actor_name = obfuscation.actor_identity(&commit_event.0.actor);
+
+            match tag_bots {
+                Some(bots) => format!(
+                    "({}, '{}', {})",
+                    commit_event.0.repo_id,
+                    actor_name,
+                    bots.is_bot(&commit_event.0.actor)
+                ),
+                None => format!("({}, '{}')", commit_event.0.repo_id, actor_name),
+            }
         })
         .collect::<Vec<String>>();
+    let columns = match tag_bots {
+        Some(_) => "(repo_id, actor_name, is_bot)",
+        None => "(repo_id, actor_name)",
+    };
+    group_committer_values_sql_insert(&a, columns)
+}
+
+/// Rows of committer values, already formatted, as inserts into `columns`.
+fn group_committer_values_sql_insert(a: &[String], columns: &str) -> String {
     // Chunk together the inserts by 20 to it's less work for Postgres.
     // EG: instead of `insert into c (a, b) values (foo, bar)` many times, do this:
     // `insert into c (a, b) values (foo, bar), (foo, baz), (foo, baz2)`
-    a.chunks(20).map(|c| {
-        let collector = c.iter().cloned().map(|x| x).collect::<Vec<String>>().join(", ");
-        format!("INSERT INTO committer_repo_id_names (repo_id, actor_name) VALUES {} ON CONFLICT DO NOTHING;", collector)
-    })
-    .collect::<Vec<String>>()
-    .join("\n")
+    a.chunks(20)
+        .map(|c| {
+            let collector = c
+                .iter()
+                .cloned()
+                .map(|x| x)
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!(
+                "INSERT INTO committer_repo_id_names {} VALUES {} ON CONFLICT DO NOTHING;",
+                columns, collector
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn group_bot_sql_insert(bots: &BTreeSet<String>, obfuscation: &Obfuscation) -> String {
+    bots.iter()
+        .map(|login| format!("('{}')", obfuscation.actor_identity(login)))
+        .collect::<Vec<String>>()
+        .chunks(20)
+        .map(|c| {
+            format!(
+                "INSERT INTO bot_actors (actor_name) VALUES {} ON CONFLICT DO NOTHING;",
+                c.join(", ")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
 }
 
 // It's possible repo_id is in here twice, which causes an error from Postgres.
@@ -683,12 +1784,47 @@ WHERE repo_mapping.repo_id = EXCLUDED.repo_id AND repo_mapping.event_timestamp <
 
 #[cfg(test)]
 mod tests {
+    // The erasure report has to find mixed-case logins in the committer output
     #[test]
-    fn multi_row_insert_committers() {
+    fn deny_list_finds_mixed_case_committer() {
         use crate::group_committer_sql_insert_par;
         use rusty_von_humboldt::types::CommitEvent;
+        use rusty_von_humboldt::{DenyList, Obfuscation, OutputFormat};
         use std::collections::BTreeMap;
 
+        let mut items: BTreeMap<CommitEvent, i64> = BTreeMap::new();
+        items.insert(
+            CommitEvent {
+                actor: "SomeOne".to_string(),
+                repo_id: 1,
+            },
+            1,
+        );
+        let deny_list = DenyList::from_json(r#"{"logins": ["SomeOne"]}"#).unwrap();
+        for obfuscation in &[
+            Obfuscation::Plaintext,
+            Obfuscation::Hmac(b"secret".to_vec()),
+        ] {
+            let output = group_committer_sql_insert_par(&items, obfuscation, None);
+            assert_eq!(
+                vec![format!(
+                    "committer_repo_id_names.actor_name {}",
+                    obfuscation.actor_identity("SomeOne")
+                )],
+                deny_list
+                    .find_in_output(obfuscation, OutputFormat::Sql, &output)
+                    .unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn multi_row_insert_committers() {
+        use crate::group_committer_sql_insert_par;
+        use rusty_von_humboldt::types::CommitEvent;
+        use rusty_von_humboldt::{BotClassifier, Obfuscation};
+        use std::collections::{BTreeMap, BTreeSet};
+
         let mut items: BTreeMap<CommitEvent, i64> = BTreeMap::new();
 
         items
@@ -727,13 +1863,29 @@ mod tests {
         // group sql statement works
         let expected_sql = "INSERT INTO committer_repo_id_names (repo_id, actor_name) VALUES (1, 'bar'), (2, 'bar'), (2, 'baz'), (1, 'foo'), (2, 'foo') ON CONFLICT DO NOTHING;";
 
-        assert_eq!(expected_sql, group_committer_sql_insert_par(&items, false));
+        assert_eq!(
+            expected_sql,
+            group_committer_sql_insert_par(&items, &Obfuscation::Plaintext, None)
+        );
 
-        let expected_sql_obf = "INSERT INTO committer_repo_id_names (repo_id, actor_name) VALUES (1, '62cdb7020ff920e5aa642c3d4066950dd1f01f4d'), (2, '62cdb7020ff920e5aa642c3d4066950dd1f01f4d'), (2, 'bbe960a25ea311d21d40669e93df2003ba9b90a2'), (1, '0beec7b5ea3f0fdbc95d0dd47f3c5bc275da8a33'), (2, '0beec7b5ea3f0fdbc95d0dd47f3c5bc275da8a33') ON CONFLICT DO NOTHING;";
+        let expected_sql_obf = "INSERT INTO committer_repo_id_names (repo_id, actor_name) VALUES (1, '68c70ddb0cf1e172e9f70dc23ebc0d6fa3ed4f3102ce430f22211f0ea5439389'), (2, '68c70ddb0cf1e172e9f70dc23ebc0d6fa3ed4f3102ce430f22211f0ea5439389'), (2, 'cb9ddf25bb551017085b79c615904730544776a65f010fd3e0d88390e3553ff1'), (1, '773ba44693c7553d6ee20f61ea5d2757a9a4f4a44d2841ae4e95b52e4cd62db4'), (2, '773ba44693c7553d6ee20f61ea5d2757a9a4f4a44d2841ae4e95b52e4cd62db4') ON CONFLICT DO NOTHING;";
 
         assert_eq!(
             expected_sql_obf,
-            group_committer_sql_insert_par(&items, true)
+            group_committer_sql_insert_par(&items, &Obfuscation::Hmac(b"secret".to_vec()), None)
+        );
+
+        items
+            .entry(CommitEvent {
+                actor: "dependabot[bot]".to_string(),
+                repo_id: 1,
+            })
+            .or_insert(1);
+        let bots = BotClassifier::new(BTreeSet::new(), 100, 1000);
+        let expected_sql_tagged = "INSERT INTO committer_repo_id_names (repo_id, actor_name, is_bot) VALUES (1, 'bar', false), (2, 'bar', false), (2, 'baz', false), (1, 'dependabot[bot]', true), (1, 'foo', false), (2, 'foo', false) ON CONFLICT DO NOTHING;";
+        assert_eq!(
+            expected_sql_tagged,
+            group_committer_sql_insert_par(&items, &Obfuscation::Plaintext, Some(&bots))
         );
     }
 
@@ -0,0 +1,282 @@
+use crate::aggregate::sql_string;
+use crate::types::RepoIdToName;
+use chrono::{DateTime, TimeZone, Utc};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A row of the committer count output.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CommitterRow {
+    pub repo_id: i64,
+    /// Already obfuscated, if the shard was run with obfuscation.
+    pub actor_name: String,
+    /// Only there when bots were tagged.
+    pub is_bot: Option<bool>,
+}
+
+impl CommitterRow {
+    pub fn as_sql_values(&self) -> String {
+        let actor_name = sql_string(&Some(self.actor_name.clone()));
+        match self.is_bot {
+            Some(is_bot) => format!("({}, {}, {})", self.repo_id, actor_name, is_bot),
+            None => format!("({}, {})", self.repo_id, actor_name),
+        }
+    }
+}
+
+/// One `INSERT` statement of our SQL output.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Insert {
+    pub table: String,
+    /// Empty if the statement doesn't name them.
+    pub columns: Vec<String>,
+    /// With quoted values unquoted.
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Read a single quoted string up to the closing quote, where two quotes are one escaped quote.
+fn read_quoted(chars: &mut Peekable<Chars>, value: &mut String) -> Result<(), String> {
+    loop {
+        match chars.next() {
+            Some('\'') if chars.peek() == Some(&'\'') => {
+                chars.next();
+                value.push('\'');
+            }
+            Some('\'') => return Ok(()),
+            Some(c) => value.push(c),
+            None => return Err("Unterminated string in SQL".to_string()),
+        }
+    }
+}
+
+/// Every `INSERT ... VALUES` statement in our SQL output.
+///
+/// Only understands what we write ourselves: rows of numbers, booleans and single quoted strings.
+pub(crate) fn inserts(sql: &str) -> Result<Vec<Insert>, String> {
+    let mut inserts: Vec<Insert> = Vec::new();
+    let mut fields: Vec<String> = Vec::new();
+    let mut value = String::new();
+    let mut word = String::new();
+    let mut after_into = false;
+    let mut after_table = false;
+    let mut in_values = false;
+    let mut in_columns = false;
+    let mut in_row = false;
+    let mut chars = sql.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_row || in_columns {
+            match c {
+                '\'' => read_quoted(&mut chars, &mut value)?,
+                ',' | ')' => {
+                    fields.push(value.trim().to_string());
+                    value.clear();
+                    if c == ')' {
+                        let insert = inserts
+                            .last_mut()
+                            .ok_or_else(|| "Values outside an INSERT in SQL".to_string())?;
+                        if in_row {
+                            insert.rows.push(fields.split_off(0));
+                        } else {
+                            insert.columns = fields.split_off(0);
+                        }
+                        in_row = false;
+                        in_columns = false;
+                    }
+                }
+                c => value.push(c),
+            }
+            continue;
+        }
+
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+            continue;
+        }
+        if !word.is_empty() {
+            after_table = after_into;
+            if after_into {
+                inserts.push(Insert {
+                    table: word.clone(),
+                    columns: Vec::new(),
+                    rows: Vec::new(),
+                });
+            }
+            after_into = word.eq_ignore_ascii_case("INTO");
+            in_values = word.eq_ignore_ascii_case("VALUES");
+            word.clear();
+        }
+        match c {
+            '\'' => read_quoted(&mut chars, &mut String::new())?,
+            '(' if in_values => in_row = true,
+            '(' if after_table => in_columns = true,
+            c if !c.is_whitespace() => after_table = false,
+            _ => {}
+        }
+    }
+    if in_row || in_columns {
+        return Err("Unterminated row in SQL".to_string());
+    }
+    Ok(inserts)
+}
+
+/// The rows of every `INSERT ... VALUES` statement in our SQL output, with quoted values unquoted.
+fn value_rows(sql: &str) -> Result<Vec<Vec<String>>, String> {
+    Ok(inserts(sql)?
+        .into_iter()
+        .flat_map(|insert| insert.rows)
+        .collect())
+}
+
+/// Rows from committer count output.
+pub fn parse_committer_rows(sql: &str) -> Result<Vec<CommitterRow>, String> {
+    value_rows(sql)?
+        .into_iter()
+        .map(|fields| {
+            let repo_id = fields[0]
+                .parse::<i64>()
+                .map_err(|e| format!("Bad repo ID {}: {}", fields[0], e))?;
+            let is_bot = match fields.len() {
+                2 => None,
+                3 => Some(
+                    fields[2]
+                        .parse::<bool>()
+                        .map_err(|e| format!("Bad is_bot {}: {}", fields[2], e))?,
+                ),
+                _ => return Err(format!("Unexpected committer row {:?}", fields)),
+            };
+            Ok(CommitterRow {
+                repo_id,
+                actor_name: fields[1].clone(),
+                is_bot,
+            })
+        })
+        .collect()
+}
+
+/// Rows from repo mapping output.
+pub fn parse_repo_mapping_rows(sql: &str) -> Result<Vec<RepoIdToName>, String> {
+    value_rows(sql)?
+        .into_iter()
+        .map(|fields| {
+            if fields.len() != 3 {
+                return Err(format!("Unexpected repo mapping row {:?}", fields));
+            }
+            let repo_id = fields[0]
+                .parse::<i64>()
+                .map_err(|e| format!("Bad repo ID {}: {}", fields[0], e))?;
+            let event_timestamp: DateTime<Utc> = Utc
+                .datetime_from_str(&fields[2], "%Y-%m-%d %H:%M:%S%.f UTC")
+                .map_err(|e| format!("Bad timestamp {}: {}", fields[2], e))?;
+            Ok(RepoIdToName {
+                repo_id,
+                repo_name: fields[1].clone(),
+                event_timestamp,
+            })
+        })
+        .collect()
+}
+
+/// Each repo and committer once, sorted. A committer tagged as a bot by any shard stays tagged.
+///
+/// Shards have to agree on whether bots are tagged, since the rows go in the same table.
+pub fn merge_committer_rows(mut rows: Vec<CommitterRow>) -> Result<Vec<CommitterRow>, String> {
+    if let Some(first) = rows.first() {
+        let tagged = first.is_bot.is_some();
+        if rows.iter().any(|row| row.is_bot.is_some() != tagged) {
+            return Err(
+                "Only some shards tagged bots, please rerun them with the same BOTS".to_string(),
+            );
+        }
+    }
+    // true sorts after false, so the tagged row is the last of each pair
+    rows.sort();
+    rows.reverse();
+    rows.dedup_by(|a, b| a.repo_id == b.repo_id && a.actor_name == b.actor_name);
+    rows.reverse();
+    Ok(rows)
+}
+
+/// The latest name for each repo, which is what loading every mapping would leave in the table.
+/// Ties go to the name that sorts first so the result doesn't depend on the order shards are read.
+pub fn latest_repo_names(mut mappings: Vec<RepoIdToName>) -> Vec<RepoIdToName> {
+    mappings.sort_by(|a, b| {
+        a.repo_id
+            .cmp(&b.repo_id)
+            .then_with(|| b.event_timestamp.cmp(&a.event_timestamp))
+            .then_with(|| a.repo_name.cmp(&b.repo_name))
+    });
+    mappings.dedup_by(|a, b| a.repo_id == b.repo_id);
+    mappings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::merge::*;
+    use crate::types::RepoIdToName;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn merges_committer_rows() {
+        let shard_one = "INSERT INTO committer_repo_id_names (repo_id, actor_name, is_bot) VALUES (1, 'bar', false), (2, 'o''brien', false) ON CONFLICT DO NOTHING;\n\
+            INSERT INTO committer_repo_id_names (repo_id, actor_name, is_bot) VALUES (2, 'ci-bot', false) ON CONFLICT DO NOTHING;";
+        let shard_two = "INSERT INTO committer_repo_id_names (repo_id, actor_name, is_bot) VALUES (2, 'ci-bot', true), (1, 'bar', false) ON CONFLICT DO NOTHING;";
+
+        let mut rows = parse_committer_rows(shard_one).unwrap();
+        assert_eq!("o'brien", rows[1].actor_name);
+        rows.extend(parse_committer_rows(shard_two).unwrap());
+
+        let merged = merge_committer_rows(rows).unwrap();
+        let values: Vec<String> = merged.iter().map(CommitterRow::as_sql_values).collect();
+        assert_eq!(
+            vec![
+                "(1, 'bar', false)",
+                "(2, 'ci-bot', true)",
+                "(2, 'o''brien', false)"
+            ],
+            values
+        );
+
+        let untagged = parse_committer_rows(
+            "INSERT INTO committer_repo_id_names (repo_id, actor_name) VALUES (1, 'bar') ON CONFLICT DO NOTHING;",
+        )
+        .unwrap();
+        assert_eq!(None, untagged[0].is_bot);
+        let mut mixed = merged.clone();
+        mixed.extend(untagged);
+        assert!(merge_committer_rows(mixed).is_err());
+    }
+
+    #[test]
+    fn latest_repo_name_wins() {
+        let shard_one = "INSERT INTO repo_mapping (repo_id, repo_name, event_timestamp)
+VALUES (1, 'foo/repo-name', '2014-07-08 09:10:11 UTC'), (2, 'baz/a-repo', '2014-07-08 09:10:11 UTC')
+ON CONFLICT (repo_id) DO UPDATE SET (repo_name, event_timestamp) = (excluded.repo_name, excluded.event_timestamp)
+WHERE repo_mapping.repo_id = EXCLUDED.repo_id AND repo_mapping.event_timestamp < EXCLUDED.event_timestamp;";
+        let shard_two = "INSERT INTO repo_mapping (repo_id, repo_name, event_timestamp)
+VALUES (2, 'bar/a-repo-renamed', '2015-07-08 09:10:11 UTC')
+ON CONFLICT (repo_id) DO UPDATE SET (repo_name, event_timestamp) = (excluded.repo_name, excluded.event_timestamp)
+WHERE repo_mapping.repo_id = EXCLUDED.repo_id AND repo_mapping.event_timestamp < EXCLUDED.event_timestamp;";
+
+        let mut mappings = parse_repo_mapping_rows(shard_two).unwrap();
+        mappings.extend(parse_repo_mapping_rows(shard_one).unwrap());
+        assert_eq!(3, mappings.len());
+
+        assert_eq!(
+            vec![
+                RepoIdToName {
+                    repo_id: 1,
+                    repo_name: "foo/repo-name".to_string(),
+                    event_timestamp: Utc.ymd(2014, 7, 8).and_hms(9, 10, 11),
+                },
+                RepoIdToName {
+                    repo_id: 2,
+                    repo_name: "bar/a-repo-renamed".to_string(),
+                    event_timestamp: Utc.ymd(2015, 7, 8).and_hms(9, 10, 11),
+                },
+            ],
+            latest_repo_names(mappings)
+        );
+        assert!(parse_repo_mapping_rows("INSERT INTO repo_mapping VALUES (1, 'foo").is_err());
+    }
+}
@@ -0,0 +1,100 @@
+extern crate hmac;
+extern crate sha2;
+
+use self::hmac::{Hmac, Mac};
+use self::sha2::Sha256;
+
+/// How actor identities are written to the output. Every mode that emits actors goes through
+/// this so the same person gets the same identity across datasets and they can be joined.
+#[derive(Clone)]
+pub enum Obfuscation {
+    /// Hex HMAC-SHA256 of the login. Unlike a plain hash it can't be reversed by hashing the
+    /// public list of GitHub logins without the key.
+    Hmac(Vec<u8>),
+    /// A stable, positive integer taken from the HMAC. Same privacy as `Hmac`, smaller to store.
+    Pseudonym(Vec<u8>),
+    /// The login as GitHub has it.
+    Plaintext,
+}
+
+impl Obfuscation {
+    /// `method` is one of `hmac`, `pseudonym` or `plaintext`. The keyed methods need a non-empty key.
+    pub fn new(method: &str, key: Option<Vec<u8>>) -> Result<Obfuscation, String> {
+        let key = key.filter(|key| !key.is_empty());
+        match (method, key) {
+            ("plaintext", _) => Ok(Obfuscation::Plaintext),
+            ("hmac", Some(key)) => Ok(Obfuscation::Hmac(key)),
+            ("pseudonym", Some(key)) => Ok(Obfuscation::Pseudonym(key)),
+            ("hmac", None) | ("pseudonym", None) => {
+                Err(format!("A secret key is needed for {} obfuscation", method))
+            }
+            (other, _) => Err(format!(
+                "Unknown obfuscation {}, expected hmac, pseudonym or plaintext",
+                other
+            )),
+        }
+    }
+
+    /// What to write in place of the actor's login.
+    pub fn actor_identity(&self, login: &str) -> String {
+        match self {
+            Obfuscation::Hmac(key) => hmac_sha256(key, login)
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+            Obfuscation::Pseudonym(key) => {
+                let digest = hmac_sha256(key, login);
+                let mut id: i64 = 0;
+                for byte in &digest[..8] {
+                    id = (id << 8) | i64::from(*byte);
+                }
+                (id & i64::MAX).to_string()
+            }
+            Obfuscation::Plaintext => login.to_string(),
+        }
+    }
+}
+
+/// Keep the key out of logs.
+impl std::fmt::Debug for Obfuscation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Obfuscation::Hmac(_) => write!(f, "Hmac"),
+            Obfuscation::Pseudonym(_) => write!(f, "Pseudonym"),
+            Obfuscation::Plaintext => write!(f, "Plaintext"),
+        }
+    }
+}
+
+fn hmac_sha256(key: &[u8], login: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC takes keys of any size");
+    mac.input(login.as_bytes());
+    mac.result().code().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::obfuscation::Obfuscation;
+
+    #[test]
+    fn actor_identities() {
+        let hmac = Obfuscation::new("hmac", Some(b"secret".to_vec())).unwrap();
+        assert_eq!(
+            "773ba44693c7553d6ee20f61ea5d2757a9a4f4a44d2841ae4e95b52e4cd62db4",
+            hmac.actor_identity("foo")
+        );
+
+        let pseudonym = Obfuscation::new("pseudonym", Some(b"secret".to_vec())).unwrap();
+        assert_eq!("8591641337173333309", pseudonym.actor_identity("foo"));
+        assert_ne!(
+            pseudonym.actor_identity("foo"),
+            pseudonym.actor_identity("bar")
+        );
+
+        assert_eq!("foo", Obfuscation::Plaintext.actor_identity("foo"));
+
+        assert!(Obfuscation::new("hmac", None).is_err());
+        assert!(Obfuscation::new("pseudonym", Some(Vec::new())).is_err());
+        assert!(Obfuscation::new("sha1", None).is_err());
+    }
+}
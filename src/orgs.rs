@@ -0,0 +1,167 @@
+use crate::aggregate::{sql_string, Aggregator};
+use crate::time_bucket::TimeBucket;
+use crate::types::Event;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// What happened in an org's repos in a month.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OrgMonth {
+    pub org_login: String,
+    pub active_repos: BTreeSet<i64>,
+    pub committers: BTreeSet<String>,
+    pub merged_prs: u64,
+    pub new_repos: u64,
+}
+
+/// Per org per month activity from the `org` 2015 and later events carry.
+///
+/// Distinct counts can't be added up across flushes so nothing's written until the end, and a
+/// month's row replaces what an earlier run wrote for it, so main only takes runs of whole months.
+#[derive(Debug, Clone, Default)]
+pub struct OrgRollups {
+    months: BTreeMap<(i64, DateTime<Utc>), OrgMonth>,
+}
+
+impl OrgRollups {
+    pub fn month(&self, org_id: i64, at: DateTime<Utc>) -> Option<&OrgMonth> {
+        self.months.get(&(org_id, TimeBucket::Month.start(at)))
+    }
+}
+
+impl Aggregator for OrgRollups {
+    fn observe(&mut self, event: &Event) {
+        let org = match event.org {
+            Some(ref org) if org.id != -1 => org,
+            _ => return,
+        };
+        let month = self
+            .months
+            .entry((org.id, TimeBucket::Month.start(event.created_at)))
+            .or_default();
+        if month.org_login.is_empty() {
+            month.org_login = org.login.clone().unwrap_or_default();
+        }
+        if event.repo.id != -1 {
+            month.active_repos.insert(event.repo.id);
+        }
+        if event.is_commit_event() {
+            let commit_event = event.as_commit_event();
+            if !commit_event.actor.is_empty() {
+                month.committers.insert(commit_event.actor);
+            }
+        }
+        if event.is_accepted_pr() {
+            month.merged_prs += 1;
+        }
+        let created_repo = event
+            .payload
+            .as_ref()
+            .and_then(|payload| payload.ref_type.as_deref())
+            == Some("repository");
+        if event.event_type == "CreateEvent" && created_repo {
+            month.new_repos += 1;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.months.len()
+    }
+
+    fn can_flush_early(&self) -> bool {
+        false
+    }
+
+    fn drain(&mut self) -> String {
+        let rows: Vec<String> = self
+            .months
+            .iter()
+            .map(|((org_id, month), org_month)| {
+                format!(
+                    "({}, {}, '{}', {}, {}, {}, {})",
+                    org_id,
+                    sql_string(&Some(org_month.org_login.clone())),
+                    month,
+                    org_month.active_repos.len(),
+                    org_month.committers.len(),
+                    org_month.merged_prs,
+                    org_month.new_repos
+                )
+            })
+            .collect();
+        self.months.clear();
+        rows.chunks(20)
+            .map(|chunk| {
+                format!("INSERT INTO org_rollups (org_id, org_login, month, active_repos, committers, merged_prs, new_repos) VALUES {} \
+                    ON CONFLICT (org_id, month) DO UPDATE SET \
+                    org_login = EXCLUDED.org_login, \
+                    active_repos = EXCLUDED.active_repos, \
+                    committers = EXCLUDED.committers, \
+                    merged_prs = EXCLUDED.merged_prs, \
+                    new_repos = EXCLUDED.new_repos;", chunk.join(", "))
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregator;
+    use crate::orgs::OrgRollups;
+    use crate::test_events::json_event;
+    use crate::types::Event;
+    use chrono::{TimeZone, Utc};
+
+    fn org_event(event_type: &str, login: &str, repo_id: i64, payload: &str) -> Event {
+        let fields = format!(
+            r#""org": {{"id": 9, "login": "foo"}}, "payload": {}"#,
+            payload
+        );
+        json_event(event_type, login, repo_id, &fields, "2017-05-01T07:00:00Z")
+    }
+
+    #[test]
+    fn org_month_rollup() {
+        let mut rollups = OrgRollups::default();
+        rollups.observe(&org_event("PushEvent", "alice", 1, r#"{"size": 2}"#));
+        let mut quoted = org_event("PushEvent", "alice", 1, r#"{"size": 1}"#);
+        quoted.org.as_mut().unwrap().login = Some("o'brien".to_string());
+        quoted.org.as_mut().unwrap().id = 10;
+        rollups.observe(&quoted);
+        rollups.observe(&org_event("PushEvent", "alice", 2, r#"{"size": 1}"#));
+        rollups.observe(&org_event(
+            "PullRequestEvent",
+            "alice",
+            1,
+            r#"{"action": "closed", "pull_request": {"merged": true, "user": {"id": 2, "login": "bob"}}}"#,
+        ));
+        rollups.observe(&org_event(
+            "CreateEvent",
+            "alice",
+            3,
+            r#"{"ref_type": "repository"}"#,
+        ));
+        rollups.observe(&org_event(
+            "CreateEvent",
+            "alice",
+            3,
+            r#"{"ref_type": "branch"}"#,
+        ));
+        // no org
+        rollups.observe(&Event::new());
+
+        let month = rollups
+            .month(9, Utc.ymd(2017, 5, 20).and_hms(0, 0, 0))
+            .unwrap();
+        assert_eq!(3, month.active_repos.len());
+        assert_eq!(2, month.committers.len());
+        assert_eq!((1, 1), (month.merged_prs, month.new_repos));
+
+        let sql = rollups.drain();
+        assert!(sql.contains(
+            "(9, 'foo', '2017-05-01 00:00:00 UTC', 3, 2, 1, 1), \
+            (10, 'o''brien', '2017-05-01 00:00:00 UTC', 1, 1, 0, 0) ON CONFLICT (org_id, month)"
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+use crate::aggregate::Aggregator;
+use crate::time_bucket::TimeBucket;
+use crate::types::Event;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::BTreeMap;
+
+/// Repo to repo edges weighted by how many committers committed to both, over the days up to
+/// the newest event in the run. Written as CSV.
+///
+/// Committers to more than `max_repos` repos in the window are left out: they'd add an edge for
+/// every pair of their repos and say little about how those repos are related.
+/// The window depends on the last event, so nothing's written until the end. Commits that have
+/// fallen out of the window are dropped once a day of events has gone by, so memory is bounded by
+/// the committers and repos active in the window rather than the whole run.
+#[derive(Debug, Clone)]
+pub struct ContributorOverlap {
+    window: Duration,
+    max_repos: usize,
+    /// Latest commit per committer per repo.
+    last_commits: BTreeMap<String, BTreeMap<i64, DateTime<Utc>>>,
+    newest: Option<DateTime<Utc>>,
+    /// Start of the day commits before which have been dropped.
+    pruned_before: Option<DateTime<Utc>>,
+    /// Committers left out at the last drain for being over `max_repos`.
+    pub capped: usize,
+}
+
+impl ContributorOverlap {
+    pub fn new(window_days: i64, max_repos: usize) -> ContributorOverlap {
+        ContributorOverlap {
+            window: Duration::days(window_days),
+            max_repos,
+            last_commits: BTreeMap::new(),
+            newest: None,
+            pruned_before: None,
+            capped: 0,
+        }
+    }
+
+    /// Forget commits from before `before`, and committers left without any.
+    fn prune(&mut self, before: DateTime<Utc>) {
+        for repos in self.last_commits.values_mut() {
+            repos.retain(|_, last_commit| *last_commit >= before);
+        }
+        self.last_commits.retain(|_, repos| !repos.is_empty());
+        self.pruned_before = Some(before);
+    }
+
+    /// Shared committers per pair of repos, with the lower repo ID first.
+    pub fn edges(&mut self) -> BTreeMap<(i64, i64), u64> {
+        let mut edges = BTreeMap::new();
+        self.capped = 0;
+        let window_start = match self.newest {
+            Some(newest) => newest - self.window,
+            None => return edges,
+        };
+        for repos in self.last_commits.values() {
+            let in_window: Vec<i64> = repos
+                .iter()
+                .filter(|(_, last_commit)| **last_commit >= window_start)
+                .map(|(repo_id, _)| *repo_id)
+                .collect();
+            if in_window.len() > self.max_repos {
+                self.capped += 1;
+                continue;
+            }
+            // repo IDs come out of the map sorted
+            for (i, repo_a) in in_window.iter().enumerate() {
+                for repo_b in &in_window[i + 1..] {
+                    *edges.entry((*repo_a, *repo_b)).or_insert(0) += 1;
+                }
+            }
+        }
+        edges
+    }
+}
+
+impl Aggregator for ContributorOverlap {
+    fn observe(&mut self, event: &Event) {
+        if !event.is_commit_event() {
+            return;
+        }
+        let commit_event = event.as_commit_event();
+        if commit_event.actor.is_empty() || commit_event.repo_id == -1 {
+            return;
+        }
+        if self.newest < Some(event.created_at) {
+            self.newest = Some(event.created_at);
+        }
+        let window_start = self.newest.expect("we've just seen a commit") - self.window;
+        if event.created_at < window_start {
+            return;
+        }
+        let prune_before = TimeBucket::Day.start(window_start);
+        if self.pruned_before < Some(prune_before) {
+            self.prune(prune_before);
+        }
+        let last_commit = self
+            .last_commits
+            .entry(commit_event.actor)
+            .or_default()
+            .entry(commit_event.repo_id)
+            .or_insert(event.created_at);
+        if event.created_at > *last_commit {
+            *last_commit = event.created_at;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.last_commits.len()
+    }
+
+    fn can_flush_early(&self) -> bool {
+        false
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "csv"
+    }
+
+    /// Edges as CSV with a header row.
+    fn drain(&mut self) -> String {
+        let mut csv = "repo_id_a,repo_id_b,shared_committers\n".to_string();
+        for ((repo_a, repo_b), shared) in self.edges() {
+            csv.push_str(&format!("{},{},{}\n", repo_a, repo_b, shared));
+        }
+        if self.capped > 0 {
+            info!(
+                "Left {} committers with more than {} repos out of the overlap graph.",
+                self.capped, self.max_repos
+            );
+        }
+        self.last_commits.clear();
+        self.newest = None;
+        self.pruned_before = None;
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregator;
+    use crate::overlap::ContributorOverlap;
+    use crate::test_events::{may, push_by};
+
+    #[test]
+    fn shared_committers_become_edges() {
+        let mut overlap = ContributorOverlap::new(7, 3);
+        overlap.observe(&push_by("dave", 4, 1, may(2, 7)));
+        overlap.observe(&push_by("alice", 2, 1, may(22, 7)));
+        // dave's only commit left the window
+        assert_eq!(1, overlap.len());
+        overlap.observe(&push_by("alice", 1, 1, may(21, 7)));
+        overlap.observe(&push_by("bob", 1, 1, may(22, 7)));
+        overlap.observe(&push_by("bob", 2, 1, may(23, 7)));
+        overlap.observe(&push_by("bob", 3, 1, may(23, 7)));
+        // outside the window
+        overlap.observe(&push_by("carol", 1, 1, may(1, 7)));
+        overlap.observe(&push_by("carol", 3, 1, may(1, 7)));
+        // over the cap
+        for repo_id in 1..5 {
+            overlap.observe(&push_by("robot", repo_id, 1, may(28, 7)));
+        }
+
+        assert_eq!(
+            "repo_id_a,repo_id_b,shared_committers\n1,2,2\n1,3,1\n2,3,1\n",
+            overlap.drain()
+        );
+        assert_eq!(1, overlap.capped);
+        assert_eq!("csv", overlap.file_extension());
+    }
+}
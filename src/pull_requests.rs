@@ -0,0 +1,335 @@
+use crate::aggregate::{grouped_inserts, sql_string, sql_timestamp, Aggregator};
+use crate::obfuscation::Obfuscation;
+use crate::types::{Actor, Event};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+/// Per repo PR counts over everything loaded into `pull_requests`, from each PR's final row.
+pub const PULL_REQUEST_REPO_STATS_VIEW: &str = "CREATE OR REPLACE VIEW pull_request_repo_stats AS \
+    SELECT repo_id, COUNT(*) AS opened, COUNT(merged_at) AS merged, \
+    COUNT(*) FILTER (WHERE closed_at IS NOT NULL AND merged_at IS NULL) AS closed_without_merge, \
+    COALESCE(SUM(EXTRACT(EPOCH FROM merged_at - opened_at)), 0)::bigint AS seconds_to_merge, \
+    COALESCE(SUM(review_comments), 0) AS review_comments \
+    FROM pull_requests GROUP BY repo_id;";
+
+/// What we know about one pull request from its opened, closed and reopened events.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PullRequestRecord {
+    /// Obfuscated like committer logins.
+    pub author: Option<String>,
+    pub opened_at: Option<DateTime<Utc>>,
+    pub closed_at: Option<DateTime<Utc>>,
+    pub merged_at: Option<DateTime<Utc>>,
+    pub merged_by: Option<String>,
+    pub review_comments: Option<i64>,
+    /// When the event that closed or reopened it last happened, so an older event can't undo it.
+    pub state_at: Option<DateTime<Utc>>,
+}
+
+impl PullRequestRecord {
+    pub fn closed_without_merge(&self) -> bool {
+        self.closed_at.is_some() && self.merged_at.is_none()
+    }
+
+    pub fn seconds_to_merge(&self) -> Option<i64> {
+        match (self.opened_at, self.merged_at) {
+            (Some(opened_at), Some(merged_at)) => Some((merged_at - opened_at).num_seconds()),
+            _ => None,
+        }
+    }
+}
+
+/// PR counts for a repo, worked out from each PR's final record the same way as
+/// `PULL_REQUEST_REPO_STATS_VIEW`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PullRequestRepoStats {
+    /// Every PR seen opened or closed.
+    pub opened: u64,
+    pub merged: u64,
+    pub closed_without_merge: u64,
+    /// Total over merged PRs, divide by `merged` for the mean.
+    pub seconds_to_merge: i64,
+    pub review_comments: i64,
+}
+
+/// Joins `PullRequestEvent`s by repo ID and PR number into per PR records.
+///
+/// Records are upserted, so a PR opened in one flush and closed in a later one (or run) ends up
+/// in one row, and whether it's closed comes from the latest close or reopen wherever that was.
+/// Per repo counts are a view over the records, written with them, so a PR closed, reopened and
+/// merged is only counted as merged.
+pub struct PullRequestLifecycles<'a> {
+    obfuscation: &'a Obfuscation,
+    pull_requests: BTreeMap<(i64, i64), PullRequestRecord>,
+}
+
+impl<'a> PullRequestLifecycles<'a> {
+    pub fn new(obfuscation: &'a Obfuscation) -> PullRequestLifecycles<'a> {
+        PullRequestLifecycles {
+            obfuscation,
+            pull_requests: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&self, repo_id: i64, number: i64) -> Option<&PullRequestRecord> {
+        self.pull_requests.get(&(repo_id, number))
+    }
+
+    pub fn repo_stats(&self, repo_id: i64) -> PullRequestRepoStats {
+        let mut stats = PullRequestRepoStats::default();
+        for record in self
+            .pull_requests
+            .range((repo_id, i64::MIN)..=(repo_id, i64::MAX))
+            .map(|(_, record)| record)
+        {
+            stats.opened += 1;
+            if record.merged_at.is_some() {
+                stats.merged += 1;
+                stats.seconds_to_merge += record.seconds_to_merge().unwrap_or(0);
+            }
+            if record.closed_without_merge() {
+                stats.closed_without_merge += 1;
+            }
+            stats.review_comments += record.review_comments.unwrap_or(0);
+        }
+        stats
+    }
+
+    fn identity(&self, actor: Option<&Actor>) -> Option<String> {
+        actor
+            .and_then(|actor| actor.login.as_ref())
+            .map(|login| self.obfuscation.actor_identity(login))
+    }
+}
+
+impl<'a> Aggregator for PullRequestLifecycles<'a> {
+    fn observe(&mut self, event: &Event) {
+        if event.event_type != "PullRequestEvent" || event.repo.id == -1 {
+            return;
+        }
+        let payload = match event.payload {
+            Some(ref payload) => payload,
+            None => return,
+        };
+        let pr = match payload.pull_request {
+            Some(ref pr) => pr,
+            None => return,
+        };
+        let number = match payload.number.or(pr.number) {
+            Some(number) => number,
+            None => return,
+        };
+        let closed = match payload.action.as_deref() {
+            Some("opened") | Some("reopened") => false,
+            Some("closed") => true,
+            _ => return,
+        };
+
+        let author = self.identity(pr.actor.as_ref());
+        let merged_by = self.identity(pr.merged_by.as_ref());
+        let record = self
+            .pull_requests
+            .entry((event.repo.id, number))
+            .or_default();
+        record.author = record.author.take().or(author);
+        record.opened_at = record.opened_at.or(pr.created_at);
+        if payload.action.as_deref() == Some("opened") {
+            record.opened_at = record.opened_at.or(Some(event.created_at));
+        }
+        // The two download threads don't keep events in order, the latest one decides.
+        match record.state_at {
+            Some(state_at) if event.created_at < state_at => return,
+            _ => record.state_at = Some(event.created_at),
+        }
+        if !closed {
+            record.closed_at = None;
+            record.merged_at = None;
+            record.merged_by = None;
+            return;
+        }
+
+        record.closed_at = pr.closed_at.or(Some(event.created_at));
+        record.review_comments = pr.review_comments;
+        if pr.merged.unwrap_or(false) || pr.merged_at.is_some() {
+            record.merged_at = pr.merged_at.or(Some(event.created_at));
+            record.merged_by = merged_by;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.pull_requests.len()
+    }
+
+    fn drain(&mut self) -> String {
+        let mut statements = group_pull_request_sql_insert(&self.pull_requests);
+        statements.push(PULL_REQUEST_REPO_STATS_VIEW.to_string());
+        self.pull_requests.clear();
+        statements.join("\n")
+    }
+}
+
+fn group_pull_request_sql_insert(
+    pull_requests: &BTreeMap<(i64, i64), PullRequestRecord>,
+) -> Vec<String> {
+    let rows: Vec<String> = pull_requests
+        .iter()
+        .map(|((repo_id, number), record)| {
+            format!(
+                "({}, {}, {}, {}, {}, {}, {}, {}, {})",
+                repo_id,
+                number,
+                sql_string(&record.author),
+                sql_timestamp(record.opened_at),
+                sql_timestamp(record.closed_at),
+                sql_timestamp(record.merged_at),
+                sql_string(&record.merged_by),
+                match record.review_comments {
+                    Some(review_comments) => review_comments.to_string(),
+                    None => "NULL".to_string(),
+                },
+                sql_timestamp(record.state_at)
+            )
+        })
+        .collect();
+    // Later events know more about a PR, but one from an earlier hour loaded later mustn't undo
+    // a close or reopen. A reopen clears the close, so the close columns follow whichever row
+    // has the latest state change rather than whichever has them set.
+    grouped_inserts(
+        "pull_requests (repo_id, pr_number, author, opened_at, closed_at, merged_at, merged_by, review_comments, state_at)",
+        &rows,
+        "ON CONFLICT (repo_id, pr_number) DO UPDATE SET \
+        author = COALESCE(pull_requests.author, EXCLUDED.author), \
+        opened_at = LEAST(pull_requests.opened_at, EXCLUDED.opened_at), \
+        closed_at = CASE WHEN pull_requests.state_at IS NULL OR EXCLUDED.state_at >= pull_requests.state_at THEN EXCLUDED.closed_at ELSE pull_requests.closed_at END, \
+        merged_at = CASE WHEN pull_requests.state_at IS NULL OR EXCLUDED.state_at >= pull_requests.state_at THEN EXCLUDED.merged_at ELSE pull_requests.merged_at END, \
+        merged_by = CASE WHEN pull_requests.state_at IS NULL OR EXCLUDED.state_at >= pull_requests.state_at THEN EXCLUDED.merged_by ELSE pull_requests.merged_by END, \
+        review_comments = COALESCE(EXCLUDED.review_comments, pull_requests.review_comments), \
+        state_at = GREATEST(pull_requests.state_at, EXCLUDED.state_at)",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregator;
+    use crate::obfuscation::Obfuscation;
+    use crate::pull_requests::{PullRequestLifecycles, PULL_REQUEST_REPO_STATS_VIEW};
+    use crate::test_events::json_event;
+    use crate::types::Event;
+    use chrono::{TimeZone, Utc};
+
+    fn pr_event(action: &str, number: i64, pull_request: &str, created_at: &str) -> Event {
+        let payload = format!(
+            r#""payload": {{"action": "{}", "number": {}, "pull_request": {}}}"#,
+            action, number, pull_request
+        );
+        json_event("PullRequestEvent", "owner", 155, &payload, created_at)
+    }
+
+    #[test]
+    fn opened_and_closed_are_joined() {
+        let obfuscation = Obfuscation::Plaintext;
+        let mut lifecycles = PullRequestLifecycles::new(&obfuscation);
+        lifecycles.observe(&pr_event(
+            "opened",
+            7,
+            r#"{"user": {"id": 5, "login": "author"}, "created_at": "2017-05-01T07:00:00Z"}"#,
+            "2017-05-01T07:00:00Z",
+        ));
+        lifecycles.observe(&pr_event(
+            "closed",
+            7,
+            r#"{"user": {"id": 5, "login": "author"}, "merged": true, "review_comments": 3,
+            "created_at": "2017-05-01T07:00:00Z", "closed_at": "2017-05-02T09:00:00Z",
+            "merged_at": "2017-05-02T09:00:00Z", "merged_by": {"id": 1, "login": "owner"}}"#,
+            "2017-05-02T09:00:00Z",
+        ));
+        lifecycles.observe(&pr_event(
+            "closed",
+            8,
+            r#"{"user": {"id": 6, "login": "other"}, "merged": false,
+            "created_at": "2017-04-01T07:00:00Z", "closed_at": "2017-05-02T10:00:00Z"}"#,
+            "2017-05-02T10:00:00Z",
+        ));
+
+        let merged = lifecycles.record(155, 7).unwrap();
+        assert_eq!(Some("author".to_string()), merged.author);
+        assert_eq!(Some("owner".to_string()), merged.merged_by);
+        assert_eq!(Some(26 * 60 * 60), merged.seconds_to_merge());
+        assert!(!merged.closed_without_merge());
+
+        let unmerged = lifecycles.record(155, 8).unwrap();
+        assert!(unmerged.closed_without_merge());
+        assert_eq!(
+            Some(Utc.ymd(2017, 4, 1).and_hms(7, 0, 0)),
+            unmerged.opened_at
+        );
+
+        let stats = lifecycles.repo_stats(155);
+        // PR 8 was opened before these hours, but it's still a PR seen
+        assert_eq!(
+            (2, 1, 1),
+            (stats.opened, stats.merged, stats.closed_without_merge)
+        );
+        assert_eq!(26 * 60 * 60, stats.seconds_to_merge);
+        assert_eq!(3, stats.review_comments);
+
+        let sql = lifecycles.drain();
+        assert!(sql.contains("(155, 7, 'author', '2017-05-01 07:00:00 UTC', '2017-05-02 09:00:00 UTC', '2017-05-02 09:00:00 UTC', 'owner', 3, '2017-05-02 09:00:00 UTC')"));
+        assert!(sql.contains("(155, 8, 'other', '2017-04-01 07:00:00 UTC', '2017-05-02 10:00:00 UTC', NULL, NULL, NULL, '2017-05-02 10:00:00 UTC')"));
+        assert!(sql.ends_with(PULL_REQUEST_REPO_STATS_VIEW));
+        assert!(lifecycles.is_empty());
+    }
+
+    // Closed, reopened and then merged is one merged PR, whatever order the events come in
+    #[test]
+    fn reopened_pull_requests_count_once() {
+        let obfuscation = Obfuscation::Plaintext;
+        let closed = pr_event(
+            "closed",
+            7,
+            r#"{"user": {"id": 5, "login": "author"}, "merged": false,
+            "created_at": "2017-05-01T07:00:00Z", "closed_at": "2017-05-01T08:00:00Z"}"#,
+            "2017-05-01T08:00:00Z",
+        );
+        let reopened = pr_event(
+            "reopened",
+            7,
+            r#"{"user": {"id": 5, "login": "author"}, "created_at": "2017-05-01T07:00:00Z"}"#,
+            "2017-05-01T09:00:00Z",
+        );
+        let merged = pr_event(
+            "closed",
+            7,
+            r#"{"user": {"id": 5, "login": "author"}, "merged": true,
+            "created_at": "2017-05-01T07:00:00Z", "closed_at": "2017-05-01T10:00:00Z",
+            "merged_at": "2017-05-01T10:00:00Z", "merged_by": {"id": 1, "login": "owner"}}"#,
+            "2017-05-01T10:00:00Z",
+        );
+        for events in &[
+            vec![&closed, &reopened, &merged],
+            vec![&merged, &closed, &reopened],
+        ] {
+            let mut lifecycles = PullRequestLifecycles::new(&obfuscation);
+            for event in events {
+                lifecycles.observe(event);
+            }
+            let stats = lifecycles.repo_stats(155);
+            assert_eq!(
+                (1, 1, 0),
+                (stats.opened, stats.merged, stats.closed_without_merge)
+            );
+            assert_eq!(3 * 60 * 60, stats.seconds_to_merge);
+        }
+
+        // A reopen still open at the end of a flush clears the close already loaded
+        let mut lifecycles = PullRequestLifecycles::new(&obfuscation);
+        lifecycles.observe(&closed);
+        lifecycles.observe(&reopened);
+        let record = lifecycles.record(155, 7).unwrap();
+        assert_eq!(None, record.closed_at);
+        assert_eq!(Some(Utc.ymd(2017, 5, 1).and_hms(9, 0, 0)), record.state_at);
+        let sql = lifecycles.drain();
+        assert!(sql.contains("(155, 7, 'author', '2017-05-01 07:00:00 UTC', NULL, NULL, NULL, NULL, '2017-05-01 09:00:00 UTC')"));
+        assert!(sql.contains("closed_at = CASE WHEN pull_requests.state_at IS NULL OR EXCLUDED.state_at >= pull_requests.state_at THEN EXCLUDED.closed_at ELSE pull_requests.closed_at END"));
+    }
+}
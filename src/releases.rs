@@ -0,0 +1,203 @@
+use crate::aggregate::{grouped_inserts, sql_string, Aggregator};
+use crate::obfuscation::Obfuscation;
+use crate::types::Event;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+/// `release_cadence` over every release loaded so far: how many each repo has had, the median
+/// days between them and the last one.
+pub const RELEASE_CADENCE_VIEW: &str = "CREATE OR REPLACE VIEW release_cadence AS \
+    SELECT repo_id, COUNT(*) AS releases, \
+    percentile_cont(0.5) WITHIN GROUP (ORDER BY days_since_previous) AS median_days_between, \
+    MAX(released_at) AS last_release_at \
+    FROM (SELECT repo_id, released_at, \
+    EXTRACT(EPOCH FROM released_at - LAG(released_at) OVER (PARTITION BY repo_id ORDER BY released_at)) / 86400 \
+    AS days_since_previous FROM releases) r \
+    GROUP BY repo_id;";
+
+/// A release, from a `ReleaseEvent` or a tag `CreateEvent`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReleaseRecord {
+    /// Only release events say, tags alone leave it unknown.
+    pub prerelease: Option<bool>,
+    pub released_at: DateTime<Utc>,
+    /// Obfuscated like committer logins.
+    pub actor: Option<String>,
+}
+
+/// Releases per repo keyed by tag, with a repo's tag and release events for the same tag merged
+/// into one record.
+///
+/// Records merge with what earlier flushes and runs wrote for the same tag, keeping the earliest
+/// release and whoever made it. Cadence comes from every release loaded, through
+/// `RELEASE_CADENCE_VIEW`, written after the rows.
+pub struct Releases<'a> {
+    obfuscation: &'a Obfuscation,
+    releases: BTreeMap<(i64, String), ReleaseRecord>,
+}
+
+impl<'a> Releases<'a> {
+    pub fn new(obfuscation: &'a Obfuscation) -> Releases<'a> {
+        Releases {
+            obfuscation,
+            releases: BTreeMap::new(),
+        }
+    }
+
+    pub fn release(&self, repo_id: i64, tag: &str) -> Option<&ReleaseRecord> {
+        self.releases.get(&(repo_id, tag.to_string()))
+    }
+}
+
+impl<'a> Aggregator for Releases<'a> {
+    fn observe(&mut self, event: &Event) {
+        if event.repo.id == -1 {
+            return;
+        }
+        let payload = match event.payload {
+            Some(ref payload) => payload,
+            None => return,
+        };
+        let (tag, prerelease, released_at) = match event.event_type.as_str() {
+            "ReleaseEvent" => match payload.release {
+                Some(ref release) => match release.tag_name {
+                    Some(ref tag) => (
+                        tag,
+                        release.prerelease,
+                        release.published_at.unwrap_or(event.created_at),
+                    ),
+                    None => return,
+                },
+                None => return,
+            },
+            "CreateEvent" if payload.ref_type.as_deref() == Some("tag") => match payload.git_ref {
+                Some(ref tag) => (tag, None, event.created_at),
+                None => return,
+            },
+            _ => return,
+        };
+        let actor = event
+            .actor
+            .login
+            .as_ref()
+            .map(|login| self.obfuscation.actor_identity(login));
+        let record = self
+            .releases
+            .entry((event.repo.id, tag.clone()))
+            .or_insert(ReleaseRecord {
+                prerelease,
+                released_at,
+                actor: actor.clone(),
+            });
+        record.prerelease = record.prerelease.or(prerelease);
+        if released_at < record.released_at {
+            record.released_at = released_at;
+            record.actor = actor;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.releases.len()
+    }
+
+    fn drain(&mut self) -> String {
+        let release_rows: Vec<String> = self
+            .releases
+            .iter()
+            .map(|((repo_id, tag), release)| {
+                format!(
+                    "({}, {}, {}, '{}', {})",
+                    repo_id,
+                    sql_string(&Some(tag.clone())),
+                    match release.prerelease {
+                        Some(prerelease) => prerelease.to_string(),
+                        None => "NULL".to_string(),
+                    },
+                    release.released_at,
+                    sql_string(&release.actor)
+                )
+            })
+            .collect();
+        self.releases.clear();
+
+        let mut statements = grouped_inserts(
+            "releases (repo_id, tag_name, prerelease, released_at, actor_name)",
+            &release_rows,
+            "ON CONFLICT (repo_id, tag_name) DO UPDATE SET \
+            prerelease = COALESCE(EXCLUDED.prerelease, releases.prerelease), \
+            released_at = LEAST(releases.released_at, EXCLUDED.released_at), \
+            actor_name = CASE WHEN EXCLUDED.released_at < releases.released_at \
+            THEN EXCLUDED.actor_name ELSE releases.actor_name END",
+        );
+        statements.push(RELEASE_CADENCE_VIEW.to_string());
+        statements.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregator;
+    use crate::obfuscation::Obfuscation;
+    use crate::releases::Releases;
+    use crate::test_events::json_event;
+    use crate::types::Event;
+
+    fn event(event_type: &str, payload: &str, created_at: &str) -> Event {
+        let payload = format!(r#""payload": {}"#, payload);
+        json_event(event_type, "maintainer", 155, &payload, created_at)
+    }
+
+    #[test]
+    fn tag_and_release_are_merged() {
+        let mut releases = Releases::new(&Obfuscation::Plaintext);
+        releases.observe(&event(
+            "CreateEvent",
+            r#"{"ref": "v1.0", "ref_type": "tag"}"#,
+            "2017-05-01T07:00:00Z",
+        ));
+        releases.observe(&event(
+            "ReleaseEvent",
+            r#"{"action": "published", "release": {"tag_name": "v1.0", "prerelease": false, "published_at": "2017-05-01T07:05:00Z"}}"#,
+            "2017-05-01T07:05:00Z",
+        ));
+        releases.observe(&event(
+            "CreateEvent",
+            r#"{"ref": "it's-a-tag", "ref_type": "tag"}"#,
+            "2017-05-03T07:00:00Z",
+        ));
+        releases.observe(&event(
+            "CreateEvent",
+            r#"{"ref": "feature", "ref_type": "branch"}"#,
+            "2017-05-04T07:00:00Z",
+        ));
+
+        let release = releases.release(155, "v1.0").unwrap();
+        assert_eq!(Some(false), release.prerelease);
+        assert_eq!("2017-05-01 07:00:00 UTC", release.released_at.to_string());
+        assert_eq!(2, releases.len());
+
+        let sql = releases.drain();
+        assert!(sql.contains("(155, 'it''s-a-tag', NULL, '2017-05-03 07:00:00 UTC', 'maintainer')"));
+        assert!(sql.contains("(155, 'v1.0', false, '2017-05-01 07:00:00 UTC', 'maintainer')"));
+        assert!(sql.ends_with("GROUP BY repo_id;"));
+        assert!(releases.is_empty());
+    }
+
+    // A later run that finds an earlier release of the tag takes its time and actor too
+    #[test]
+    fn earlier_release_wins_across_runs() {
+        let mut releases = Releases::new(&Obfuscation::Plaintext);
+        releases.observe(&event(
+            "CreateEvent",
+            r#"{"ref": "v2.0", "ref_type": "tag"}"#,
+            "2017-06-01T07:00:00Z",
+        ));
+        let sql = releases.drain();
+        assert!(sql.contains(
+            "released_at = LEAST(releases.released_at, EXCLUDED.released_at), \
+            actor_name = CASE WHEN EXCLUDED.released_at < releases.released_at \
+            THEN EXCLUDED.actor_name ELSE releases.actor_name END;"
+        ));
+        assert!(!sql.contains("INSERT INTO release_cadence"));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::aggregate::{sql_string, Aggregator};
+use crate::types::Event;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+/// Names that have belonged to more than one repo ID, such as a repo deleted and recreated or
+/// renamed with another taking its old name, with when each ID had the name. GitHub names are
+/// case insensitive so they're compared lowercased.
+pub const REPO_NAME_REUSE_VIEW: &str = "CREATE OR REPLACE VIEW repo_name_reuse AS \
+    SELECT h.repo_id, lower(h.repo_name) AS repo_name, \
+    MIN(h.first_seen) AS first_seen, MAX(h.last_seen) AS last_seen \
+    FROM repo_name_history h \
+    WHERE lower(h.repo_name) IN (SELECT lower(o.repo_name) FROM repo_name_history o \
+    GROUP BY lower(o.repo_name) HAVING COUNT(DISTINCT o.repo_id) > 1) \
+    GROUP BY h.repo_id, lower(h.repo_name);";
+
+/// First and last time something was seen.
+type Seen = (DateTime<Utc>, DateTime<Utc>);
+
+fn extend_seen(seen: &mut Seen, at: DateTime<Utc>) {
+    if at < seen.0 {
+        seen.0 = at;
+    }
+    if at > seen.1 {
+        seen.1 = at;
+    }
+}
+
+/// When a repository was seen with a name.
+#[derive(Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
+pub struct RepoNameSpan {
+    pub repo_id: i64,
+    pub repo_name: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Every name each repo ID has had and when we first and last saw it with that name.
+///
+/// Spans are upserted so the earliest first seen and latest last seen win across flushes and runs,
+/// and `REPO_NAME_REUSE_VIEW`, written with them, finds reused names over everything loaded.
+#[derive(Debug, Clone, Default)]
+pub struct RepoNameHistory {
+    spans: BTreeMap<(i64, String), Seen>,
+}
+
+impl RepoNameHistory {
+    pub fn spans(&self) -> Vec<RepoNameSpan> {
+        self.spans
+            .iter()
+            .map(
+                |((repo_id, repo_name), (first_seen, last_seen))| RepoNameSpan {
+                    repo_id: *repo_id,
+                    repo_name: repo_name.clone(),
+                    first_seen: *first_seen,
+                    last_seen: *last_seen,
+                },
+            )
+            .collect()
+    }
+}
+
+impl Aggregator for RepoNameHistory {
+    fn observe(&mut self, event: &Event) {
+        if event.repo.id == -1 || event.repo.name.is_empty() {
+            return;
+        }
+        let span = self
+            .spans
+            .entry((event.repo.id, event.repo.name.clone()))
+            .or_insert((event.created_at, event.created_at));
+        extend_seen(span, event.created_at);
+    }
+
+    fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    fn drain(&mut self) -> String {
+        let sql = group_repo_name_span_sql_insert("repo_name_history", &self.spans());
+        self.spans.clear();
+        format!("{}\n{}", sql, REPO_NAME_REUSE_VIEW)
+    }
+}
+
+fn group_repo_name_span_sql_insert(table: &str, spans: &[RepoNameSpan]) -> String {
+    spans
+        .chunks(20)
+        .map(|chunk| {
+            let rows = chunk
+                .iter()
+                .map(|span| {
+                    format!(
+                        "({}, {}, '{}', '{}')",
+                        span.repo_id,
+                        sql_string(&Some(span.repo_name.clone())),
+                        span.first_seen,
+                        span.last_seen
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!(
+                "INSERT INTO {table} (repo_id, repo_name, first_seen, last_seen) VALUES {rows} \
+                ON CONFLICT (repo_id, repo_name) DO UPDATE SET \
+                first_seen = LEAST({table}.first_seen, EXCLUDED.first_seen), \
+                last_seen = GREATEST({table}.last_seen, EXCLUDED.last_seen);",
+                table = table,
+                rows = rows
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregator;
+    use crate::merge::inserts;
+    use crate::repo_history::{RepoNameHistory, REPO_NAME_REUSE_VIEW};
+    use crate::test_events::{event_for, may};
+    use crate::types::Event;
+
+    fn named(repo_id: i64, repo_name: &str, day: u32) -> Event {
+        let mut event = event_for("PushEvent", repo_id, may(day, 7));
+        event.repo.name = repo_name.to_string();
+        event
+    }
+
+    #[test]
+    fn renames_are_kept() {
+        let mut history = RepoNameHistory::default();
+        history.observe(&named(1, "foo/bar", 3));
+        history.observe(&named(1, "foo/bar", 1));
+        history.observe(&named(1, "foo/baz", 5));
+        history.observe(&named(1, "foo/bar", 2));
+        history.observe(&named(2, "o'brien/bar", 2));
+
+        let spans = history.spans();
+        assert_eq!(3, spans.len());
+        assert_eq!("foo/bar", spans[0].repo_name);
+        assert_eq!(may(1, 7), spans[0].first_seen);
+        assert_eq!(may(3, 7), spans[0].last_seen);
+        assert_eq!("foo/baz", spans[1].repo_name);
+
+        let expected =
+            "INSERT INTO repo_name_history (repo_id, repo_name, first_seen, last_seen) VALUES \
+            (1, 'foo/bar', '2017-05-01 07:00:00 UTC', '2017-05-03 07:00:00 UTC'), \
+            (1, 'foo/baz', '2017-05-05 07:00:00 UTC', '2017-05-05 07:00:00 UTC'), \
+            (2, 'o''brien/bar', '2017-05-02 07:00:00 UTC', '2017-05-02 07:00:00 UTC') \
+            ON CONFLICT (repo_id, repo_name) DO UPDATE SET \
+            first_seen = LEAST(repo_name_history.first_seen, EXCLUDED.first_seen), \
+            last_seen = GREATEST(repo_name_history.last_seen, EXCLUDED.last_seen);\n";
+        assert_eq!(
+            format!("{}{}", expected, REPO_NAME_REUSE_VIEW),
+            history.drain()
+        );
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn later_runs_widen_the_span() {
+        let mut first_run = RepoNameHistory::default();
+        first_run.observe(&named(1, "foo/bar", 2));
+        let mut second_run = RepoNameHistory::default();
+        second_run.observe(&named(1, "foo/bar", 9));
+        second_run.observe(&named(1, "foo/bar", 1));
+
+        let rows: Vec<Vec<String>> = [first_run.drain(), second_run.drain()]
+            .iter()
+            .flat_map(|sql| inserts(sql).unwrap())
+            .filter(|insert| insert.table == "repo_name_history")
+            .flat_map(|insert| insert.rows)
+            .collect();
+        // one key, which the upsert widens to the 1st up to the 9th
+        assert_eq!(rows[0][..2], rows[1][..2]);
+        assert_eq!("2017-05-02 07:00:00 UTC", rows[0][2]);
+        assert_eq!("2017-05-01 07:00:00 UTC", rows[1][2]);
+        assert_eq!("2017-05-09 07:00:00 UTC", rows[1][3]);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::aggregate::{sql_string, sql_timestamp, Aggregator};
+use crate::types::Event;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+/// A point in a repo's life and what it was called then.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepoMoment {
+    pub at: DateTime<Utc>,
+    pub name: String,
+}
+
+impl RepoMoment {
+    fn of(event: &Event) -> RepoMoment {
+        RepoMoment {
+            at: event.created_at,
+            name: event.repo.name.clone(),
+        }
+    }
+}
+
+/// When a repo first and last had events, was created and was made public.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepoLifecycle {
+    pub first_event: RepoMoment,
+    /// From a `CreateEvent` for the repository itself.
+    pub created: Option<RepoMoment>,
+    /// From a `PublicEvent`, when a private repo was open sourced.
+    pub published: Option<RepoMoment>,
+    pub last_event: RepoMoment,
+}
+
+fn keep_earliest(current: &mut Option<RepoMoment>, event: &Event) {
+    let earlier = match current {
+        Some(moment) => event.created_at < moment.at,
+        None => true,
+    };
+    if earlier {
+        *current = Some(RepoMoment::of(event));
+    }
+}
+
+/// Creation, publication and first and last activity per repo ID, to spot abandoned and newly
+/// open sourced projects.
+///
+/// Rows are upserted so the earliest and latest moments win across flushes and runs.
+#[derive(Debug, Clone, Default)]
+pub struct RepoLifecycles {
+    repos: BTreeMap<i64, RepoLifecycle>,
+}
+
+impl RepoLifecycles {
+    pub fn lifecycle(&self, repo_id: i64) -> Option<&RepoLifecycle> {
+        self.repos.get(&repo_id)
+    }
+}
+
+impl Aggregator for RepoLifecycles {
+    fn observe(&mut self, event: &Event) {
+        if event.repo.id == -1 || event.repo.name.is_empty() {
+            return;
+        }
+        let lifecycle = self
+            .repos
+            .entry(event.repo.id)
+            .or_insert_with(|| RepoLifecycle {
+                first_event: RepoMoment::of(event),
+                created: None,
+                published: None,
+                last_event: RepoMoment::of(event),
+            });
+        if event.created_at < lifecycle.first_event.at {
+            lifecycle.first_event = RepoMoment::of(event);
+        }
+        if event.created_at > lifecycle.last_event.at {
+            lifecycle.last_event = RepoMoment::of(event);
+        }
+        let created_repo = event
+            .payload
+            .as_ref()
+            .and_then(|payload| payload.ref_type.as_deref())
+            == Some("repository");
+        if event.event_type == "CreateEvent" && created_repo {
+            keep_earliest(&mut lifecycle.created, event);
+        }
+        if event.event_type == "PublicEvent" {
+            keep_earliest(&mut lifecycle.published, event);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.repos.len()
+    }
+
+    fn drain(&mut self) -> String {
+        let rows: Vec<String> = self
+            .repos
+            .iter()
+            .map(|(repo_id, lifecycle)| {
+                format!(
+                    "({}, '{}', '{}', {}, {}, {}, {}, '{}', '{}')",
+                    repo_id,
+                    lifecycle.first_event.at,
+                    lifecycle.first_event.name,
+                    sql_timestamp(lifecycle.created.as_ref().map(|m| m.at)),
+                    sql_string(&lifecycle.created.as_ref().map(|m| m.name.clone())),
+                    sql_timestamp(lifecycle.published.as_ref().map(|m| m.at)),
+                    sql_string(&lifecycle.published.as_ref().map(|m| m.name.clone())),
+                    lifecycle.last_event.at,
+                    lifecycle.last_event.name
+                )
+            })
+            .collect();
+        self.repos.clear();
+        // Every SET sees the row as it was, so the names are picked by comparing the old timestamps.
+        rows.chunks(20)
+            .map(|chunk| {
+                format!("INSERT INTO repo_lifecycle (repo_id, first_event_at, first_event_name, created_at, created_name, published_at, published_name, last_event_at, last_event_name) VALUES {} \
+                    ON CONFLICT (repo_id) DO UPDATE SET \
+                    first_event_at = LEAST(repo_lifecycle.first_event_at, EXCLUDED.first_event_at), \
+                    first_event_name = CASE WHEN EXCLUDED.first_event_at < repo_lifecycle.first_event_at THEN EXCLUDED.first_event_name ELSE repo_lifecycle.first_event_name END, \
+                    created_at = LEAST(repo_lifecycle.created_at, EXCLUDED.created_at), \
+                    created_name = CASE WHEN repo_lifecycle.created_at IS NULL OR EXCLUDED.created_at < repo_lifecycle.created_at THEN EXCLUDED.created_name ELSE repo_lifecycle.created_name END, \
+                    published_at = LEAST(repo_lifecycle.published_at, EXCLUDED.published_at), \
+                    published_name = CASE WHEN repo_lifecycle.published_at IS NULL OR EXCLUDED.published_at < repo_lifecycle.published_at THEN EXCLUDED.published_name ELSE repo_lifecycle.published_name END, \
+                    last_event_at = GREATEST(repo_lifecycle.last_event_at, EXCLUDED.last_event_at), \
+                    last_event_name = CASE WHEN EXCLUDED.last_event_at > repo_lifecycle.last_event_at THEN EXCLUDED.last_event_name ELSE repo_lifecycle.last_event_name END;", chunk.join(", "))
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregator;
+    use crate::repo_lifecycle::RepoLifecycles;
+    use crate::test_events::json_event;
+    use crate::types::Event;
+
+    fn repo_event(event_type: &str, name: &str, payload: &str, created_at: &str) -> Event {
+        let payload = format!(r#""payload": {}"#, payload);
+        let mut event = json_event(event_type, "someone", 155, &payload, created_at);
+        event.repo.name = name.to_string();
+        event
+    }
+
+    #[test]
+    fn lifecycle_moments() {
+        let mut lifecycles = RepoLifecycles::default();
+        lifecycles.observe(&repo_event(
+            "PushEvent",
+            "foo/bar",
+            r#"{"size": 1}"#,
+            "2017-05-02T07:00:00Z",
+        ));
+        lifecycles.observe(&repo_event(
+            "CreateEvent",
+            "foo/bar",
+            r#"{"ref_type": "branch"}"#,
+            "2017-05-01T08:00:00Z",
+        ));
+        lifecycles.observe(&repo_event(
+            "CreateEvent",
+            "foo/old",
+            r#"{"ref_type": "repository"}"#,
+            "2017-05-01T07:00:00Z",
+        ));
+        lifecycles.observe(&repo_event(
+            "PublicEvent",
+            "foo/new",
+            "{}",
+            "2017-06-01T07:00:00Z",
+        ));
+
+        let lifecycle = lifecycles.lifecycle(155).unwrap();
+        assert_eq!("foo/old", lifecycle.first_event.name);
+        assert_eq!("foo/old", lifecycle.created.as_ref().unwrap().name);
+        assert_eq!("foo/new", lifecycle.published.as_ref().unwrap().name);
+        assert_eq!("foo/new", lifecycle.last_event.name);
+
+        let sql = lifecycles.drain();
+        assert!(sql.contains("VALUES (155, '2017-05-01 07:00:00 UTC', 'foo/old', '2017-05-01 07:00:00 UTC', 'foo/old', \
+            '2017-06-01 07:00:00 UTC', 'foo/new', '2017-06-01 07:00:00 UTC', 'foo/new') ON CONFLICT (repo_id)"));
+
+        lifecycles.observe(&repo_event(
+            "WatchEvent",
+            "foo/new",
+            "{}",
+            "2017-06-02T07:00:00Z",
+        ));
+        let sql = lifecycles.drain();
+        assert!(sql.contains("NULL, NULL, NULL, NULL"));
+    }
+}
@@ -0,0 +1,277 @@
+extern crate serde_json;
+
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Paths of the fields the 2015 and later types in `types.rs` deserialize.
+/// Keep this in sync with `Event` and friends or the drift report will call new fields ignored.
+pub const KNOWN_EVENT_FIELDS: &[&str] = &[
+    "id",
+    "type",
+    "created_at",
+    "actor",
+    "actor.id",
+    "actor.login",
+    "repo",
+    "repo.id",
+    "repo.name",
+    "org",
+    "org.id",
+    "org.login",
+    "payload",
+    "payload.action",
+    "payload.number",
+    "payload.commits",
+    "payload.push_id",
+    "payload.size",
+    "payload.distinct_size",
+    "payload.head",
+    "payload.before",
+    "payload.ref_type",
+    "payload.ref",
+    "payload.release",
+    "payload.release.tag_name",
+    "payload.release.prerelease",
+    "payload.release.published_at",
+    "payload.forkee",
+    "payload.forkee.id",
+    "payload.forkee.owner",
+    "payload.forkee.owner.id",
+    "payload.forkee.owner.login",
+    "payload.issue",
+    "payload.issue.number",
+    "payload.issue.user",
+    "payload.issue.user.id",
+    "payload.issue.user.login",
+    "payload.issue.state",
+    "payload.issue.comments",
+    "payload.issue.created_at",
+    "payload.issue.closed_at",
+    "payload.issue.pull_request",
+    "payload.issue.pull_request.url",
+    "payload.pull_request",
+    "payload.pull_request.merged",
+    "payload.pull_request.user",
+    "payload.pull_request.user.id",
+    "payload.pull_request.user.login",
+    "payload.pull_request.number",
+    "payload.pull_request.created_at",
+    "payload.pull_request.closed_at",
+    "payload.pull_request.merged_at",
+    "payload.pull_request.merged_by",
+    "payload.pull_request.merged_by.id",
+    "payload.pull_request.merged_by.login",
+    "payload.pull_request.review_comments",
+];
+
+/// Which fields showed up in the raw JSON, per event type per month.
+///
+/// We only look inside objects we deserialize: a field we ignore is recorded but not what's in it.
+#[derive(Debug, Clone, Default)]
+pub struct SchemaObservations {
+    /// (event type, month) to number of events seen.
+    pub events: BTreeMap<(String, String), u64>,
+    /// (event type, month) to field path to number of events with that field.
+    pub fields: BTreeMap<(String, String), BTreeMap<String, u64>>,
+}
+
+impl SchemaObservations {
+    pub fn observe(&mut self, event: &Value) {
+        let event_type = match event.get("type").and_then(Value::as_str) {
+            Some(event_type) => event_type.to_string(),
+            None => "n/a".to_string(),
+        };
+        // "2017-05-01T07:00:00Z" -> "2017-05"
+        let month = match event.get("created_at").and_then(Value::as_str) {
+            Some(created_at) if created_at.len() >= 7 => created_at[..7].to_string(),
+            _ => "n/a".to_string(),
+        };
+        let key = (event_type, month);
+        *self.events.entry(key.clone()).or_insert(0) += 1;
+
+        let mut paths = Vec::new();
+        collect_field_paths(event, "", &mut paths);
+        let fields = self.fields.entry(key).or_default();
+        for path in paths {
+            *fields.entry(path).or_insert(0) += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: SchemaObservations) {
+        for (key, count) in other.events {
+            *self.events.entry(key).or_insert(0) += count;
+        }
+        for (key, other_fields) in other.fields {
+            let fields = self.fields.entry(key).or_default();
+            for (path, count) in other_fields {
+                *fields.entry(path).or_insert(0) += count;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Compare what we saw with what we deserialize.
+    pub fn report(&self) -> SchemaReport {
+        let known: BTreeSet<&str> = KNOWN_EVENT_FIELDS.iter().cloned().collect();
+        let mut report = SchemaReport::default();
+
+        for ((event_type, month), event_count) in &self.events {
+            let fields = self.fields.get(&(event_type.clone(), month.clone()));
+            report.months.push(MonthlyFields {
+                event_type: event_type.clone(),
+                month: month.clone(),
+                events: *event_count,
+                fields: fields.cloned().unwrap_or_default(),
+            });
+        }
+
+        // BTreeMap ordering walks each event type's months in order.
+        let mut by_type: BTreeMap<&str, Vec<&MonthlyFields>> = BTreeMap::new();
+        for month in &report.months {
+            by_type.entry(&month.event_type).or_default().push(month);
+        }
+
+        let mut ignored = Vec::new();
+        let mut disappeared = Vec::new();
+        for (event_type, months) in by_type {
+            let mut first_seen: BTreeMap<&str, (&str, u64)> = BTreeMap::new();
+            for month in &months {
+                for (path, count) in &month.fields {
+                    let entry = first_seen.entry(path).or_insert((&month.month, 0));
+                    entry.1 += count;
+                }
+            }
+            for (path, (month, count)) in &first_seen {
+                if !known.contains(path) {
+                    ignored.push(IgnoredField {
+                        event_type: event_type.to_string(),
+                        field: path.to_string(),
+                        first_seen: month.to_string(),
+                        count: *count,
+                    });
+                }
+            }
+
+            let mut last_seen: BTreeMap<&str, &str> = BTreeMap::new();
+            for month in &months {
+                for (path, seen_in) in &last_seen {
+                    if !month.fields.contains_key(*path) {
+                        disappeared.push(DisappearedField {
+                            event_type: event_type.to_string(),
+                            field: path.to_string(),
+                            last_seen: seen_in.to_string(),
+                            missing_from: month.month.clone(),
+                            known: known.contains(path),
+                        });
+                    }
+                }
+                last_seen.retain(|path, _| month.fields.contains_key(*path));
+                for path in month.fields.keys() {
+                    last_seen.insert(path, &month.month);
+                }
+            }
+        }
+        report.ignored = ignored;
+        report.disappeared = disappeared;
+        report
+    }
+}
+
+/// Record `prefix.field` for every field in the object, descending only into fields we deserialize.
+fn collect_field_paths(value: &Value, prefix: &str, paths: &mut Vec<String>) {
+    if let Value::Object(map) = value {
+        for (field, inner) in map {
+            let path = if prefix.is_empty() {
+                field.to_string()
+            } else {
+                format!("{}.{}", prefix, field)
+            };
+            if KNOWN_EVENT_FIELDS.contains(&path.as_str()) {
+                collect_field_paths(inner, &path, paths);
+            }
+            paths.push(path);
+        }
+    }
+}
+
+/// Fields seen for one event type in one month and how many events had them.
+#[derive(Serialize, Debug, Clone)]
+pub struct MonthlyFields {
+    pub event_type: String,
+    pub month: String,
+    pub events: u64,
+    pub fields: BTreeMap<String, u64>,
+}
+
+/// A field GHA sends that we don't deserialize.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct IgnoredField {
+    pub event_type: String,
+    pub field: String,
+    pub first_seen: String,
+    pub count: u64,
+}
+
+/// A field that was there one month and gone the next time we saw that event type.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DisappearedField {
+    pub event_type: String,
+    pub field: String,
+    pub last_seen: String,
+    pub missing_from: String,
+    /// True if it's a field we deserialize, which means our numbers are probably affected.
+    pub known: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SchemaReport {
+    pub months: Vec<MonthlyFields>,
+    pub ignored: Vec<IgnoredField>,
+    pub disappeared: Vec<DisappearedField>,
+}
+
+impl SchemaReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Couldn't serialize schema report")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::SchemaObservations;
+
+    // Commits dropped out of push payloads, the field they replaced it with is one we ignore.
+    #[test]
+    fn push_payload_drift() {
+        let mut observations = SchemaObservations::default();
+        let older: serde_json::Value = serde_json::from_str(
+            r#"{"id": "1", "type": "PushEvent", "created_at": "2017-05-01T07:00:00Z",
+            "actor": {"id": 1, "login": "a"}, "repo": {"id": 2, "name": "a/b"},
+            "payload": {"size": 1, "commits": [{"sha": "abc"}]}}"#,
+        )
+        .unwrap();
+        let newer: serde_json::Value = serde_json::from_str(
+            r#"{"id": "2", "type": "PushEvent", "created_at": "2017-06-01T07:00:00Z",
+            "actor": {"id": 1, "login": "a"}, "repo": {"id": 2, "name": "a/b"},
+            "payload": {"size": 1, "push_id": 5, "repository_id": 2}}"#,
+        )
+        .unwrap();
+        observations.observe(&older);
+        observations.observe(&newer);
+
+        let report = observations.report();
+        assert_eq!(2, report.months.len());
+
+        let ignored: Vec<&str> = report.ignored.iter().map(|f| f.field.as_str()).collect();
+        assert_eq!(vec!["payload.repository_id"], ignored);
+
+        assert_eq!(1, report.disappeared.len());
+        assert_eq!("payload.commits", report.disappeared[0].field);
+        assert_eq!("2017-05", report.disappeared[0].last_seen);
+        assert_eq!("2017-06", report.disappeared[0].missing_from);
+        assert!(report.disappeared[0].known);
+    }
+}
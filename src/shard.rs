@@ -0,0 +1,166 @@
+use crate::gha_sources::sort_chronologically;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// One of `count` machines splitting the source files between them, numbered from 1.
+///
+/// Each shard takes a contiguous run of the file list sorted by hour, so it covers a contiguous
+/// time range, and between them the shards cover every file exactly once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Shard {
+    /// This shard's part of the source files.
+    pub fn select(self, mut files: Vec<String>) -> Vec<String> {
+        sort_chronologically(&mut files);
+        let start = files.len() * (self.index - 1) / self.count;
+        let end = files.len() * self.index / self.count;
+        files.truncate(end);
+        files.split_off(start)
+    }
+
+    /// Directory under the year the shard's output goes in.
+    pub fn dir_name(self) -> String {
+        format!("shard_{}_of_{}", self.index, self.count)
+    }
+
+    /// The shard a directory from `dir_name` is for.
+    pub fn from_dir_name(dir_name: &str) -> Result<Shard, String> {
+        let invalid = || {
+            format!(
+                "Expected a shard directory like shard_2_of_4, got {}",
+                dir_name
+            )
+        };
+        if !dir_name.starts_with("shard_") {
+            return Err(invalid());
+        }
+        let shard = dir_name["shard_".len()..].replacen("_of_", "/", 1);
+        shard.parse::<Shard>().map_err(|_| invalid())
+    }
+}
+
+/// The number of shards if `shards` is every shard of a single split, 1 to N of N. A missing
+/// shard or shards from splits with different counts would merge into something no single run
+/// would have written.
+pub fn whole_split(shards: &BTreeSet<Shard>) -> Result<usize, String> {
+    let count = match shards.iter().next() {
+        Some(shard) => shard.count,
+        None => return Err("No shard output found".to_string()),
+    };
+    if shards.iter().any(|shard| shard.count != count) {
+        let counts: BTreeSet<usize> = shards.iter().map(|shard| shard.count).collect();
+        return Err(format!(
+            "Found shards from splits of {:?}, please remove all but one",
+            counts
+        ));
+    }
+    let missing: Vec<String> = (1..=count)
+        .map(|index| Shard { index, count })
+        .filter(|shard| !shards.contains(shard))
+        .map(|shard| shard.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("Missing output from shards {}", missing.join(", ")));
+    }
+    Ok(count)
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Shard, String> {
+        let invalid = || format!("Expected a shard like 2/4, got {}", s);
+        let mut parts = s.splitn(2, '/');
+        let index = parts
+            .next()
+            .and_then(|index| index.trim().parse::<usize>().ok())
+            .ok_or_else(invalid)?;
+        let count = parts
+            .next()
+            .and_then(|count| count.trim().parse::<usize>().ok())
+            .ok_or_else(invalid)?;
+        if index == 0 || index > count {
+            return Err(format!(
+                "Shard {} is out of range, shards are numbered from 1 to {}",
+                index, count
+            ));
+        }
+        Ok(Shard { index, count })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gha_sources::sort_chronologically;
+    use crate::shard::{whole_split, Shard};
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn shards_cover_every_file_once() {
+        // by name, the way S3 lists them
+        let mut files: Vec<String> = (0..12)
+            .map(|hour| format!("2016-01-01-{}.json.gz", hour))
+            .collect();
+        files.sort();
+        let shards: Vec<Vec<String>> = (1..=3)
+            .map(|index| Shard { index, count: 3 }.select(files.clone()))
+            .collect();
+        assert_eq!(
+            vec![4, 4, 4],
+            shards.iter().map(Vec::len).collect::<Vec<usize>>()
+        );
+        assert_eq!(
+            vec![
+                "2016-01-01-4.json.gz",
+                "2016-01-01-5.json.gz",
+                "2016-01-01-6.json.gz",
+                "2016-01-01-7.json.gz",
+            ],
+            shards[1]
+        );
+
+        let mut sorted = files.clone();
+        sort_chronologically(&mut sorted);
+        assert_eq!(sorted, shards.concat());
+
+        assert_eq!(Ok(Shard { index: 2, count: 4 }), "2/4".parse::<Shard>());
+        assert_eq!("shard_2_of_4", Shard { index: 2, count: 4 }.dir_name());
+        assert!("0/4".parse::<Shard>().is_err());
+        assert!("5/4".parse::<Shard>().is_err());
+        assert!("2".parse::<Shard>().is_err());
+    }
+
+    #[test]
+    fn merge_needs_every_shard_of_one_split() {
+        let shards = |dir_names: &[&str]| -> BTreeSet<Shard> {
+            dir_names
+                .iter()
+                .map(|dir_name| Shard::from_dir_name(dir_name).unwrap())
+                .collect()
+        };
+        assert_eq!(
+            Ok(3),
+            whole_split(&shards(&["shard_2_of_3", "shard_1_of_3", "shard_3_of_3"]))
+        );
+        assert_eq!(
+            Err("Missing output from shards 2/3".to_string()),
+            whole_split(&shards(&["shard_1_of_3", "shard_3_of_3"]))
+        );
+        assert!(whole_split(&shards(&["shard_1_of_2", "shard_2_of_2", "shard_1_of_3"])).is_err());
+        assert!(whole_split(&BTreeSet::new()).is_err());
+        assert!(Shard::from_dir_name("shard_0_of_3").is_err());
+        assert!(Shard::from_dir_name("shard_1_of_x").is_err());
+        assert!(Shard::from_dir_name("01.txt.gz").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use crate::aggregate::Aggregator;
+use crate::time_bucket::TimeBucket;
+use crate::types::Event;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+/// New stars and forks for a repo in one time bucket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StarForkCount {
+    pub stars: u64,
+    pub forks: u64,
+}
+
+/// Counts `WatchEvent`s (which are stars, not watches) and `ForkEvent`s per repo per time bucket.
+///
+/// Rows are keyed on the bucket size as well as its start, so runs with different `TIMEBUCKET`s
+/// can share the table. Counts are added to what's already in the table, so loads aren't
+/// idempotent: loading the same hours twice counts them twice.
+#[derive(Debug, Clone)]
+pub struct StarsForks {
+    bucket: TimeBucket,
+    counts: BTreeMap<(i64, DateTime<Utc>), StarForkCount>,
+}
+
+impl StarsForks {
+    pub fn new(bucket: TimeBucket) -> StarsForks {
+        StarsForks {
+            bucket,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn count(&self, repo_id: i64, at: DateTime<Utc>) -> StarForkCount {
+        self.counts
+            .get(&(repo_id, self.bucket.start(at)))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+impl Aggregator for StarsForks {
+    fn observe(&mut self, event: &Event) {
+        if event.repo.id == -1 {
+            return;
+        }
+        let is_star = event.event_type == "WatchEvent";
+        if !is_star && event.event_type != "ForkEvent" {
+            return;
+        }
+        let count = self
+            .counts
+            .entry((event.repo.id, self.bucket.start(event.created_at)))
+            .or_default();
+        if is_star {
+            count.stars += 1;
+        } else {
+            count.forks += 1;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    fn drain(&mut self) -> String {
+        let rows: Vec<String> = self
+            .counts
+            .iter()
+            .map(|((repo_id, bucket_start), count)| {
+                format!(
+                    "({}, '{}', '{}', {}, {})",
+                    repo_id,
+                    self.bucket.name(),
+                    bucket_start,
+                    count.stars,
+                    count.forks
+                )
+            })
+            .collect();
+        self.counts.clear();
+        rows.chunks(20)
+            .map(|chunk| {
+                format!(
+                    "INSERT INTO stars_forks (repo_id, bucket, bucket_start, stars, forks) VALUES {} \
+                    ON CONFLICT (repo_id, bucket, bucket_start) DO UPDATE SET \
+                    stars = stars_forks.stars + EXCLUDED.stars, \
+                    forks = stars_forks.forks + EXCLUDED.forks;",
+                    chunk.join(", ")
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregator;
+    use crate::merge::inserts;
+    use crate::stars_forks::StarsForks;
+    use crate::test_events::{event_for, may};
+    use crate::time_bucket::TimeBucket;
+
+    #[test]
+    fn stars_and_forks_per_day() {
+        let mut stars_forks = StarsForks::new(TimeBucket::Day);
+        stars_forks.observe(&event_for("WatchEvent", 1, may(1, 3)));
+        stars_forks.observe(&event_for("WatchEvent", 1, may(1, 20)));
+        stars_forks.observe(&event_for("ForkEvent", 1, may(1, 4)));
+        stars_forks.observe(&event_for("PushEvent", 1, may(1, 5)));
+        stars_forks.observe(&event_for("ForkEvent", 2, may(1, 5)));
+
+        let count = stars_forks.count(1, may(1, 0));
+        assert_eq!((2, 1), (count.stars, count.forks));
+        assert_eq!(2, stars_forks.len());
+
+        let expected = "INSERT INTO stars_forks (repo_id, bucket, bucket_start, stars, forks) VALUES \
+            (1, 'day', '2017-05-01 00:00:00 UTC', 2, 1), (2, 'day', '2017-05-01 00:00:00 UTC', 0, 1) \
+            ON CONFLICT (repo_id, bucket, bucket_start) DO UPDATE SET \
+            stars = stars_forks.stars + EXCLUDED.stars, \
+            forks = stars_forks.forks + EXCLUDED.forks;";
+        assert_eq!(expected, stars_forks.drain());
+    }
+
+    #[test]
+    fn day_split_across_runs_adds_up() {
+        // each run only has half of the day, so both write a row for it
+        let mut morning = StarsForks::new(TimeBucket::Day);
+        morning.observe(&event_for("WatchEvent", 1, may(1, 3)));
+        morning.observe(&event_for("ForkEvent", 1, may(1, 4)));
+        let mut evening = StarsForks::new(TimeBucket::Day);
+        evening.observe(&event_for("WatchEvent", 1, may(1, 20)));
+
+        let rows: Vec<Vec<String>> = [morning.drain(), evening.drain()]
+            .iter()
+            .flat_map(|sql| inserts(sql).unwrap())
+            .flat_map(|insert| insert.rows)
+            .collect();
+        assert_eq!(2, rows.len());
+        // same key, so the second run's row adds to the first's
+        assert_eq!(rows[0][..3], rows[1][..3]);
+        let stars: i64 = rows.iter().map(|row| row[3].parse::<i64>().unwrap()).sum();
+        let forks: i64 = rows.iter().map(|row| row[4].parse::<i64>().unwrap()).sum();
+        assert_eq!((2, 1), (stars, forks));
+    }
+}
@@ -0,0 +1,111 @@
+extern crate serde_json;
+
+use crate::gha_sources::ParseStats;
+
+/// A source file we gave up on after retrying.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SkippedFile {
+    pub key: String,
+    pub reason: String,
+}
+
+/// A results file we couldn't put in the destination bucket.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FailedUpload {
+    pub key: String,
+    pub reason: String,
+}
+
+/// A thread that panicked, losing whatever it had downloaded or worked out.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FailedThread {
+    pub thread: String,
+    pub reason: String,
+}
+
+/// Everything that went wrong during a run that means the output is missing data, plus how
+/// parsing each source file went. Written as JSON next to the output so we can tell a clean run
+/// from a lossy one.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RunSummary {
+    pub skipped_files: Vec<SkippedFile>,
+    pub failed_uploads: Vec<FailedUpload>,
+    pub failed_threads: Vec<FailedThread>,
+    pub lines_parsed: usize,
+    pub lines_failed: usize,
+    pub events_excluded: usize,
+    /// Failed lines left out of the quarantine file, over `QUARANTINEMAXLINES` for their file.
+    pub quarantine_overflow: usize,
+    /// Events dropped because we'd already seen their id, only counted with DEDUP set.
+    pub duplicates_dropped: u64,
+    pub parse_stats: Vec<ParseStats>,
+}
+
+impl RunSummary {
+    pub fn skipped_file(&mut self, key: &str, reason: &str) {
+        self.skipped_files.push(SkippedFile {
+            key: key.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    pub fn failed_upload(&mut self, key: &str, reason: &str) {
+        self.failed_uploads.push(FailedUpload {
+            key: key.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    pub fn failed_thread(&mut self, thread: &str, reason: &str) {
+        self.failed_threads.push(FailedThread {
+            thread: thread.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    pub fn parsed_file(&mut self, stats: ParseStats) {
+        self.lines_parsed += stats.parsed;
+        self.lines_failed += stats.failed;
+        self.events_excluded += stats.excluded;
+        self.quarantine_overflow += stats.quarantine_overflow;
+        self.parse_stats.push(stats);
+    }
+
+    /// Number of files, in or out, we lost, plus threads that died, each of which could have lost
+    /// any number of them.
+    pub fn failure_count(&self) -> usize {
+        self.skipped_files.len() + self.failed_uploads.len() + self.failed_threads.len()
+    }
+
+    /// True if we lost more files than we're willing to tolerate.
+    pub fn exceeds(&self, max_failures: usize) -> bool {
+        self.failure_count() > max_failures
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Couldn't serialize run summary")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::summary::RunSummary;
+
+    #[test]
+    fn failures_over_threshold() {
+        let mut summary = RunSummary::default();
+        assert!(!summary.exceeds(0));
+
+        summary.skipped_file("2017-05-01-7.json.gz", "timed out");
+        summary.failed_upload("rvh2/committers/2017/01.txt.gz", "403");
+        assert_eq!(2, summary.failure_count());
+        assert!(summary.exceeds(1));
+        assert!(!summary.exceeds(2));
+        summary.failed_thread("receiving", "panicked");
+        assert!(summary.exceeds(2));
+
+        let json = summary.to_json();
+        assert!(json.contains("\"key\": \"2017-05-01-7.json.gz\""));
+        assert!(json.contains("\"reason\": \"403\""));
+    }
+}
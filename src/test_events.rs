@@ -0,0 +1,54 @@
+//! Events for the tests, so each module doesn't need its own way of making them.
+
+use crate::types::{Event, Payload};
+use chrono::{DateTime, TimeZone, Utc};
+
+/// `hour`:00 on a day in May 2017, when most of the tests' events happen.
+pub fn may(day: u32, hour: u32) -> DateTime<Utc> {
+    Utc.ymd(2017, 5, day).and_hms(hour, 0, 0)
+}
+
+/// An event of some type on a repo, with nothing else filled in.
+pub fn event_for(event_type: &str, repo_id: i64, created_at: DateTime<Utc>) -> Event {
+    let mut event = Event::new();
+    event.id = 1;
+    event.event_type = event_type.to_string();
+    event.repo.id = repo_id;
+    event.created_at = created_at;
+    event
+}
+
+/// A push of `size` commits by `login`.
+pub fn push_by(login: &str, repo_id: i64, size: i64, created_at: DateTime<Utc>) -> Event {
+    let mut event = event_for("PushEvent", repo_id, created_at);
+    event.actor.id = 1;
+    event.actor.login = Some(login.to_string());
+    event.payload = Some(Payload {
+        push_id: Some(1),
+        size: Some(size),
+        ..Default::default()
+    });
+    event
+}
+
+/// An event parsed from the archive's JSON, on repo `foo/bar`. `fields` go in as they are, like
+/// `"payload": {"size": 1}`, for the parts the other helpers can't fill in.
+pub fn json_event(
+    event_type: &str,
+    login: &str,
+    repo_id: i64,
+    fields: &str,
+    created_at: &str,
+) -> Event {
+    let fields = if fields.is_empty() {
+        String::new()
+    } else {
+        format!(", {}", fields)
+    };
+    serde_json::from_str(&format!(
+        r#"{{"id": "1", "type": "{}", "actor": {{"id": 1, "login": "{}"}},
+        "repo": {{"id": {}, "name": "foo/bar"}}, "created_at": "{}"{}}}"#,
+        event_type, login, repo_id, created_at, fields
+    ))
+    .unwrap()
+}
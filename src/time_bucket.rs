@@ -0,0 +1,92 @@
+use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+use std::str::FromStr;
+
+/// How finely time series modes group events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeBucket {
+    Hour,
+    Day,
+    /// Weeks start on Monday.
+    Week,
+    Month,
+}
+
+impl TimeBucket {
+    pub fn name(self) -> &'static str {
+        match self {
+            TimeBucket::Hour => "hour",
+            TimeBucket::Day => "day",
+            TimeBucket::Week => "week",
+            TimeBucket::Month => "month",
+        }
+    }
+
+    /// Start of the bucket the timestamp falls in.
+    pub fn start(self, at: DateTime<Utc>) -> DateTime<Utc> {
+        let day = Utc.ymd(at.year(), at.month(), at.day());
+        match self {
+            TimeBucket::Hour => day.and_hms(at.hour(), 0, 0),
+            TimeBucket::Day => day.and_hms(0, 0, 0),
+            TimeBucket::Week => (day
+                - Duration::days(i64::from(at.weekday().num_days_from_monday())))
+            .and_hms(0, 0, 0),
+            TimeBucket::Month => Utc.ymd(at.year(), at.month(), 1).and_hms(0, 0, 0),
+        }
+    }
+
+    /// True if `start` up to `end` is made of whole buckets, without one cut off at either end.
+    pub fn whole_buckets(self, start: DateTime<Utc>, end: DateTime<Utc>) -> bool {
+        self.start(start) == start && self.start(end) == end
+    }
+}
+
+impl FromStr for TimeBucket {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TimeBucket, String> {
+        match s {
+            "hour" => Ok(TimeBucket::Hour),
+            "day" => Ok(TimeBucket::Day),
+            "week" => Ok(TimeBucket::Week),
+            "month" => Ok(TimeBucket::Month),
+            _ => Err(format!(
+                "Unknown time bucket {}, expected hour, day, week or month",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::time_bucket::TimeBucket;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn bucket_starts() {
+        // a Thursday
+        let at = Utc.ymd(2017, 6, 1).and_hms(13, 45, 10);
+        assert_eq!(
+            Utc.ymd(2017, 6, 1).and_hms(13, 0, 0),
+            TimeBucket::Hour.start(at)
+        );
+        assert_eq!(
+            Utc.ymd(2017, 6, 1).and_hms(0, 0, 0),
+            TimeBucket::Day.start(at)
+        );
+        assert_eq!(
+            Utc.ymd(2017, 5, 29).and_hms(0, 0, 0),
+            TimeBucket::Week.start(at)
+        );
+        assert_eq!(
+            Utc.ymd(2017, 6, 1).and_hms(0, 0, 0),
+            TimeBucket::Month.start(at)
+        );
+        assert!("fortnight".parse::<TimeBucket>().is_err());
+
+        let may = Utc.ymd(2017, 5, 1).and_hms(0, 0, 0);
+        assert!(TimeBucket::Month.whole_buckets(may, Utc.ymd(2017, 7, 1).and_hms(0, 0, 0)));
+        assert!(!TimeBucket::Month.whole_buckets(may, at));
+        assert!(TimeBucket::Hour.whole_buckets(may, TimeBucket::Hour.start(at)));
+    }
+}
@@ -0,0 +1,387 @@
+use crate::aggregate::Aggregator;
+use crate::time_bucket::TimeBucket;
+use crate::types::Event;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::str::FromStr;
+
+/// What repos are ranked by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopKMetric {
+    /// Distinct committers, counted the same way as the committer count.
+    Committers,
+    Stars,
+    Forks,
+    MergedPrs,
+    Pushes,
+}
+
+impl TopKMetric {
+    pub fn name(self) -> &'static str {
+        match self {
+            TopKMetric::Committers => "committers",
+            TopKMetric::Stars => "stars",
+            TopKMetric::Forks => "forks",
+            TopKMetric::MergedPrs => "merged_prs",
+            TopKMetric::Pushes => "pushes",
+        }
+    }
+
+    /// Whether the event counts towards its repo. Committers are deduplicated separately.
+    fn counts(self, event: &Event) -> bool {
+        match self {
+            TopKMetric::Committers => event.is_commit_event(),
+            TopKMetric::Stars => event.event_type == "WatchEvent",
+            TopKMetric::Forks => event.event_type == "ForkEvent",
+            TopKMetric::MergedPrs => event.is_accepted_pr(),
+            TopKMetric::Pushes => event.event_type == "PushEvent",
+        }
+    }
+}
+
+impl FromStr for TopKMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TopKMetric, String> {
+        match s {
+            "committers" => Ok(TopKMetric::Committers),
+            "stars" => Ok(TopKMetric::Stars),
+            "forks" => Ok(TopKMetric::Forks),
+            "merged_prs" => Ok(TopKMetric::MergedPrs),
+            "pushes" => Ok(TopKMetric::Pushes),
+            _ => Err(format!(
+                "Unknown top-K metric {}, expected committers, stars, forks, merged_prs or pushes",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Counter {
+    count: u64,
+    /// How much of `count` may belong to items evicted before this one took their place.
+    error: u64,
+}
+
+/// Space-Saving heavy hitters: keeps at most `capacity` repo IDs and their counts. Counts are
+/// never under the true count, and over it by no more than their error.
+#[derive(Debug, Clone)]
+pub struct SpaceSaving {
+    capacity: usize,
+    counters: HashMap<i64, Counter>,
+    by_count: BTreeSet<(u64, i64)>,
+}
+
+impl SpaceSaving {
+    pub fn new(capacity: usize) -> SpaceSaving {
+        SpaceSaving {
+            capacity,
+            counters: HashMap::new(),
+            by_count: BTreeSet::new(),
+        }
+    }
+
+    pub fn offer(&mut self, key: i64) {
+        let counter = match self.counters.get(&key) {
+            Some(counter) => {
+                self.by_count.remove(&(counter.count, key));
+                Counter {
+                    count: counter.count + 1,
+                    error: counter.error,
+                }
+            }
+            None if self.counters.len() < self.capacity => Counter { count: 1, error: 0 },
+            None => {
+                // Take over from the smallest count, which might have been ours.
+                let (min_count, min_key) = *self
+                    .by_count
+                    .iter()
+                    .next()
+                    .expect("a full sketch has counters");
+                self.by_count.remove(&(min_count, min_key));
+                self.counters.remove(&min_key);
+                Counter {
+                    count: min_count + 1,
+                    error: min_count,
+                }
+            }
+        };
+        self.counters.insert(key, counter);
+        self.by_count.insert((counter.count, key));
+    }
+
+    /// The `k` highest counts as (repo ID, count, error), highest first.
+    pub fn top(&self, k: usize) -> Vec<(i64, u64, u64)> {
+        self.by_count
+            .iter()
+            .rev()
+            .take(k)
+            .map(|(count, key)| (*key, *count, self.counters[key].error))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.counters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+}
+
+/// How a `TopK` is counting.
+#[derive(Debug, Clone)]
+enum Counting {
+    Approximate(BTreeMap<DateTime<Utc>, SpaceSaving>),
+    /// Exact counts of the candidates a first pass found, per bucket.
+    Exact {
+        candidates: BTreeMap<DateTime<Utc>, HashSet<i64>>,
+        counts: BTreeMap<DateTime<Utc>, HashMap<i64, u64>>,
+    },
+}
+
+/// The top `k` repos by a metric per time bucket, in bounded memory.
+///
+/// Counting is approximate with a Space-Saving sketch of `capacity` repos per bucket. For exact
+/// numbers, run the events through `exact_second_pass` of the first pass as well. Buckets need
+/// every event before they can be ranked so nothing's written until the end.
+///
+/// The exception is distinct committers, which remember every (bucket, repo, committer) they've
+/// counted so nobody is counted twice. That's exact but grows with the run.
+#[derive(Debug, Clone)]
+pub struct TopK {
+    metric: TopKMetric,
+    k: usize,
+    capacity: usize,
+    bucket: TimeBucket,
+    counting: Counting,
+    /// (bucket, repo, committer) we've counted, for distinct committers.
+    committers_seen: HashSet<(DateTime<Utc>, i64, String)>,
+}
+
+impl TopK {
+    pub fn new(metric: TopKMetric, k: usize, capacity: usize, bucket: TimeBucket) -> TopK {
+        TopK {
+            metric,
+            k,
+            capacity: capacity.max(k),
+            bucket,
+            counting: Counting::Approximate(BTreeMap::new()),
+            committers_seen: HashSet::new(),
+        }
+    }
+
+    /// A top-K that counts exactly, but only the repos this one's sketches are holding.
+    pub fn exact_second_pass(&self) -> TopK {
+        let candidates = match self.counting {
+            Counting::Approximate(ref sketches) => sketches
+                .iter()
+                .map(|(bucket, sketch)| {
+                    let repos = sketch.top(self.capacity).iter().map(|top| top.0).collect();
+                    (*bucket, repos)
+                })
+                .collect(),
+            Counting::Exact { ref candidates, .. } => candidates.clone(),
+        };
+        TopK {
+            counting: Counting::Exact {
+                candidates,
+                counts: BTreeMap::new(),
+            },
+            committers_seen: HashSet::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Ranked (repo ID, value, error) per bucket.
+    pub fn ranked(&self) -> BTreeMap<DateTime<Utc>, Vec<(i64, u64, u64)>> {
+        match self.counting {
+            Counting::Approximate(ref sketches) => sketches
+                .iter()
+                .map(|(bucket, sketch)| (*bucket, sketch.top(self.k)))
+                .collect(),
+            Counting::Exact { ref counts, .. } => counts
+                .iter()
+                .map(|(bucket, counts)| {
+                    let mut ranked: Vec<(i64, u64, u64)> = counts
+                        .iter()
+                        .map(|(repo_id, count)| (*repo_id, *count, 0))
+                        .collect();
+                    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+                    ranked.truncate(self.k);
+                    (*bucket, ranked)
+                })
+                .collect(),
+        }
+    }
+
+    fn first_committer(&mut self, bucket: DateTime<Utc>, event: &Event) -> bool {
+        let commit_event = event.as_commit_event();
+        if commit_event.actor.is_empty() {
+            return false;
+        }
+        self.committers_seen
+            .insert((bucket, commit_event.repo_id, commit_event.actor))
+    }
+}
+
+impl Aggregator for TopK {
+    fn observe(&mut self, event: &Event) {
+        if event.repo.id == -1 || !self.metric.counts(event) {
+            return;
+        }
+        let bucket = self.bucket.start(event.created_at);
+        if let Counting::Exact { ref candidates, .. } = self.counting {
+            let candidate = match candidates.get(&bucket) {
+                Some(repos) => repos.contains(&event.repo.id),
+                None => false,
+            };
+            if !candidate {
+                return;
+            }
+        }
+        if self.metric == TopKMetric::Committers && !self.first_committer(bucket, event) {
+            return;
+        }
+        let capacity = self.capacity;
+        match self.counting {
+            Counting::Approximate(ref mut sketches) => sketches
+                .entry(bucket)
+                .or_insert_with(|| SpaceSaving::new(capacity))
+                .offer(event.repo.id),
+            Counting::Exact { ref mut counts, .. } => {
+                *counts
+                    .entry(bucket)
+                    .or_default()
+                    .entry(event.repo.id)
+                    .or_insert(0) += 1
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self.counting {
+            Counting::Approximate(ref sketches) => sketches.len(),
+            Counting::Exact { ref counts, .. } => counts.len(),
+        }
+    }
+
+    fn can_flush_early(&self) -> bool {
+        false
+    }
+
+    /// Each bucket's ranks replace whatever an earlier run wrote for it, including ranks past the
+    /// end of a shorter list.
+    fn drain(&mut self) -> String {
+        let mut statements = Vec::new();
+        for (bucket, ranked) in self.ranked() {
+            statements.push(format!(
+                "DELETE FROM top_k WHERE metric = '{}' AND bucket = '{}' AND bucket_start = '{}';",
+                self.metric.name(),
+                self.bucket.name(),
+                bucket
+            ));
+            let rows: Vec<String> = ranked
+                .iter()
+                .enumerate()
+                .map(|(rank, (repo_id, value, error))| {
+                    format!(
+                        "('{}', '{}', '{}', {}, {}, {}, {})",
+                        self.metric.name(),
+                        self.bucket.name(),
+                        bucket,
+                        rank + 1,
+                        repo_id,
+                        value,
+                        error
+                    )
+                })
+                .collect();
+            for chunk in rows.chunks(20) {
+                statements.push(format!("INSERT INTO top_k (metric, bucket, bucket_start, rank, repo_id, value, error) VALUES {} \
+                    ON CONFLICT (metric, bucket, bucket_start, rank) DO UPDATE SET \
+                    repo_id = EXCLUDED.repo_id, \
+                    value = EXCLUDED.value, \
+                    error = EXCLUDED.error;", chunk.join(", ")));
+            }
+        }
+        match self.counting {
+            Counting::Approximate(ref mut sketches) => sketches.clear(),
+            Counting::Exact { ref mut counts, .. } => counts.clear(),
+        }
+        self.committers_seen.clear();
+        statements.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregate::Aggregator;
+    use crate::test_events::{event_for, may, push_by};
+    use crate::time_bucket::TimeBucket;
+    use crate::top_k::{SpaceSaving, TopK, TopKMetric};
+    use crate::types::Event;
+
+    #[test]
+    fn space_saving_keeps_heavy_hitters() {
+        let mut sketch = SpaceSaving::new(2);
+        for key in &[1, 1, 1, 2, 3, 1, 1, 3] {
+            sketch.offer(*key);
+        }
+        assert_eq!(2, sketch.len());
+        let top = sketch.top(2);
+        assert_eq!((1, 5, 0), top[0]);
+        // 3 took over 2's count of 1 and was seen twice
+        assert_eq!((3, 3, 1), top[1]);
+    }
+
+    fn star(repo_id: i64, day: u32) -> Event {
+        event_for("WatchEvent", repo_id, may(day, 7))
+    }
+
+    #[test]
+    fn exact_second_pass_counts_candidates() {
+        let events: Vec<Event> = vec![
+            star(1, 1),
+            star(2, 1),
+            star(1, 2),
+            star(3, 3),
+            star(1, 3),
+            star(4, 20),
+        ];
+        let mut first_pass = TopK::new(TopKMetric::Stars, 1, 2, TimeBucket::Month);
+        for event in &events {
+            first_pass.observe(event);
+        }
+        let mut second_pass = first_pass.exact_second_pass();
+        for event in &events {
+            second_pass.observe(event);
+        }
+
+        let month = may(1, 0);
+        assert_eq!(vec![(1, 3, 0)], second_pass.ranked()[&month]);
+        assert_eq!(
+            "DELETE FROM top_k WHERE metric = 'stars' AND bucket = 'month' \
+            AND bucket_start = '2017-05-01 00:00:00 UTC';\n\
+            INSERT INTO top_k (metric, bucket, bucket_start, rank, repo_id, value, error) VALUES \
+            ('stars', 'month', '2017-05-01 00:00:00 UTC', 1, 1, 3, 0) \
+            ON CONFLICT (metric, bucket, bucket_start, rank) DO UPDATE SET \
+            repo_id = EXCLUDED.repo_id, value = EXCLUDED.value, error = EXCLUDED.error;",
+            second_pass.drain()
+        );
+        assert!("downloads".parse::<TopKMetric>().is_err());
+    }
+
+    #[test]
+    fn committers_counted_once_per_bucket() {
+        let mut top_k = TopK::new(TopKMetric::Committers, 2, 10, TimeBucket::Month);
+        top_k.observe(&push_by("alice", 1, 1, may(1, 7)));
+        top_k.observe(&push_by("alice", 1, 1, may(2, 7)));
+        top_k.observe(&push_by("bob", 1, 1, may(2, 7)));
+        top_k.observe(&push_by("alice", 2, 1, may(3, 7)));
+
+        let month = may(1, 0);
+        assert_eq!(vec![(1, 2, 0), (2, 1, 0)], top_k.ranked()[&month]);
+    }
+}
@@ -1,5 +1,3 @@
-extern crate sha1;
-
 use chrono::{DateTime, TimeZone, Utc};
 use serde::de::{self, Deserialize, Deserializer};
 use serde_json::Value;
@@ -18,6 +16,14 @@ pub struct Actor {
     pub login: Option<String>,
 }
 
+/// Organization owning the repo of a 2015 and later event, if it's owned by one.
+#[derive(Deserialize, Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
+pub struct Org {
+    #[serde(default = "id_not_specified")]
+    pub id: i64,
+    pub login: Option<String>,
+}
+
 /// GitHub repository.  Assuming the ID stays constant but the name can change.
 #[derive(Deserialize, Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
 pub struct Repo {
@@ -26,12 +32,54 @@ pub struct Repo {
     pub name: String,
 }
 
-/// Pull request in an event.  We only care if it was merged or not.
-#[derive(Deserialize, Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
+/// Pull request in an event: whether it was merged for committer counts, plus when it was
+/// opened, closed and merged and by whom for PR lifecycles.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, PartialOrd, Ord, Eq)]
 pub struct PullRequest {
     pub merged: Option<bool>,
     #[serde(rename = "user")]
     pub actor: Option<Actor>,
+    pub number: Option<i64>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub closed_at: Option<DateTime<Utc>>,
+    pub merged_at: Option<DateTime<Utc>>,
+    pub merged_by: Option<Actor>,
+    pub review_comments: Option<i64>,
+}
+
+/// The new repository a fork event created.
+#[derive(Deserialize, Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
+pub struct Forkee {
+    #[serde(default = "id_not_specified")]
+    pub id: i64,
+    pub owner: Option<Actor>,
+}
+
+/// Present on issues that are really pull requests.
+#[derive(Deserialize, Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
+pub struct IssuePullRequestLinks {
+    pub url: Option<String>,
+}
+
+/// Issue in an issue or issue comment event.
+#[derive(Deserialize, Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
+pub struct Issue {
+    pub number: Option<i64>,
+    pub user: Option<Actor>,
+    pub state: Option<String>,
+    /// Including the one in this event, for comment events.
+    pub comments: Option<i64>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub closed_at: Option<DateTime<Utc>>,
+    pub pull_request: Option<IssuePullRequestLinks>,
+}
+
+/// Release in a release event.
+#[derive(Deserialize, Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
+pub struct Release {
+    pub tag_name: Option<String>,
+    pub prerelease: Option<bool>,
+    pub published_at: Option<DateTime<Utc>>,
 }
 
 /// A git commit.
@@ -41,12 +89,68 @@ pub struct Commit {
 }
 
 /// Type containing if it's a push event or pull request event.
-#[derive(Deserialize, Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
+///
+/// Push payloads have changed over the years: older ones list `commits`, later ones dropped
+/// that for `size`/`distinct_size` and the newest only have the `head` and `before` shas.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, PartialOrd, Ord, Eq)]
 pub struct Payload {
     pub action: Option<String>,
+    pub number: Option<i64>,
     #[serde(rename = "pull_request")]
     pub pull_request: Option<PullRequest>,
     pub commits: Option<Vec<Commit>>,
+    pub push_id: Option<i64>,
+    pub size: Option<i64>,
+    pub distinct_size: Option<i64>,
+    pub head: Option<String>,
+    pub before: Option<String>,
+    pub forkee: Option<Forkee>,
+    pub issue: Option<Issue>,
+    /// What a create event made: repository, branch or tag.
+    pub ref_type: Option<String>,
+    /// Name of the branch or tag a create event made.
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+    pub release: Option<Release>,
+}
+
+/// What git reports as the sha of a ref that doesn't exist, such as the head of a deleted branch.
+const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+
+impl Payload {
+    /// Did this push put commits on a ref, using whichever fields this payload era has.
+    pub fn pushed_commits(&self) -> bool {
+        if let Some(ref commits) = self.commits {
+            if !commits.is_empty() {
+                return true;
+            }
+        }
+        if let Some(size) = self.size.or(self.distinct_size) {
+            return size > 0;
+        }
+        if self.commits.is_some() {
+            return false;
+        }
+        match (&self.head, &self.before) {
+            (Some(head), Some(before)) => head != before && head != NULL_SHA,
+            _ => false,
+        }
+    }
+
+    /// How many commits were pushed. `distinct_size` skips commits already on another branch,
+    /// the `commits` list is capped at 20 so it's the last resort before assuming one.
+    pub fn pushed_commit_count(&self) -> i64 {
+        if let Some(distinct_size) = self.distinct_size {
+            return distinct_size;
+        }
+        if let Some(size) = self.size {
+            return size;
+        }
+        match self.commits {
+            Some(ref commits) if !commits.is_empty() => commits.len() as i64,
+            _ => 1,
+        }
+    }
 }
 
 /// 2015 and later github archive event.
@@ -60,6 +164,7 @@ pub struct Event {
     pub actor: Actor,
     pub repo: Repo,
     pub payload: Option<Payload>,
+    pub org: Option<Org>,
 }
 
 impl Event {
@@ -77,6 +182,7 @@ impl Event {
                 name: "n/a".to_string(),
             },
             payload: None,
+            org: None,
             created_at: Utc.ymd(2010, 1, 1).and_hms(0, 0, 0),
         }
     }
@@ -118,7 +224,8 @@ impl Event {
         }
     }
 
-    // Also covers placeholder Events made in the constructor above
+    // Also covers placeholder Events made in the constructor above, and events converted from
+    // before 2015, which never have event or actor IDs
     pub fn is_missing_data(&self) -> bool {
         if self.id == -1 || self.repo.id == -1 || self.actor.id == -1 {
             return true;
@@ -152,13 +259,21 @@ impl Event {
             return false;
         }
         match self.payload {
-            Some(ref payload) => match payload.commits {
-                Some(ref commits) => !commits.is_empty(),
-                None => false,
-            },
+            Some(ref payload) => payload.pushed_commits(),
             None => false,
         }
     }
+
+    /// Number of commits this event credits its committer with. An accepted PR counts as one.
+    pub fn commit_count(&self) -> i64 {
+        if !self.is_direct_push_event() {
+            return if self.is_accepted_pr() { 1 } else { 0 };
+        }
+        match self.payload {
+            Some(ref payload) => payload.pushed_commit_count(),
+            None => 0,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -198,10 +313,87 @@ mod tests {
         };
         let commit_event = event.as_commit_event();
 
+        assert!(event.is_commit_event());
+        assert_eq!(1, event.commit_count());
         assert_eq!("direct_committer", commit_event.actor);
         assert_eq!(255, commit_event.repo_id);
     }
 
+    fn push_with_payload(payload: &str) -> crate::types::Event {
+        let push_text = format!(
+            r#"{{"id": "1", "type": "PushEvent", "actor": {{"id": 1, "login": "a"}},
+            "repo": {{"id": 2, "name": "a/b"}}, "payload": {}, "created_at": "2017-05-01T07:00:00Z"}}"#,
+            payload
+        );
+        serde_json::from_str(&push_text).unwrap()
+    }
+
+    // Push payloads with a list of commits, some of which weren't new to the repo
+    #[test]
+    fn push_with_commits_list() {
+        let event = push_with_payload(
+            r#"{"push_id": 1, "size": 3, "distinct_size": 2, "head": "b", "before": "a",
+            "commits": [{"sha": "1"}, {"sha": "2"}, {"sha": "3"}]}"#,
+        );
+        assert!(event.is_direct_push_event());
+        assert_eq!(2, event.commit_count());
+
+        // pushing a tag or deleting a branch has no commits
+        let event =
+            push_with_payload(r#"{"push_id": 1, "size": 0, "distinct_size": 0, "commits": []}"#);
+        assert!(!event.is_direct_push_event());
+        assert_eq!(0, event.commit_count());
+    }
+
+    // Newest push payloads only have the shas on either side of the push
+    #[test]
+    fn push_with_only_head_and_before() {
+        let event = push_with_payload(
+            r#"{"push_id": 1, "ref": "refs/heads/master", "head": "b", "before": "a"}"#,
+        );
+        assert!(event.is_direct_push_event());
+        assert_eq!(1, event.commit_count());
+
+        let deleted_branch = push_with_payload(
+            r#"{"push_id": 1, "head": "0000000000000000000000000000000000000000", "before": "a"}"#,
+        );
+        assert!(!deleted_branch.is_direct_push_event());
+
+        let no_change = push_with_payload(r#"{"push_id": 1, "head": "a", "before": "a"}"#);
+        assert!(!no_change.is_direct_push_event());
+    }
+
+    // Pre-2015 push payloads only had a size
+    #[test]
+    fn pre_2015_push_counted() {
+        use crate::types::Pre2015Event;
+        let push_text = r#"{"type": "PushEvent", "actor": "old_committer", "repository": {"id": 12, "name": "foo", "owner": "someone"},
+        "payload": {"size": 2}, "created_at": "2013-01-01T12:00:24-08:00"}"#;
+        let event: Pre2015Event = serde_json::from_str(push_text).unwrap();
+        assert!(event.is_direct_push_event());
+        assert_eq!("old_committer", event.as_commit_event().actor);
+        assert_eq!(12, event.as_commit_event().repo_id);
+
+        let event = event.to_event().unwrap();
+        assert!(event.is_direct_push_event());
+        assert_eq!(2, event.commit_count());
+        assert_eq!("2013-01-01 20:00:24 UTC", event.created_at.to_string());
+        assert_eq!("someone/foo", event.repo.name);
+    }
+
+    // Old merged PRs are credited to the event's actor, same as before converting them
+    #[test]
+    fn pre_2015_merged_pr_credits_actor() {
+        use crate::types::Pre2015Event;
+        let pr_text = r#"{"type": "PullRequestEvent", "actor": "old_author", "repository": {"id": 12, "name": "foo", "owner": "someone"},
+        "payload": {"pull_request": {"merged": true}}, "created_at": "2013-01-01T12:00:24-08:00"}"#;
+        let old: Pre2015Event = serde_json::from_str(pr_text).unwrap();
+        let event = old.to_event().unwrap();
+        assert!(event.is_accepted_pr());
+        assert_eq!(old.as_commit_event(), event.as_commit_event());
+        assert_eq!("old_author", event.as_commit_event().actor);
+    }
+
     // Ensure we count the person who made the PR as a committer, not the person who accepted it:
     #[test]
     fn pull_request_committer_gets_counted() {
@@ -316,10 +508,31 @@ impl<'de> Deserialize<'de> for Pre2015Actor {
     }
 }
 
+/// Repository in a pre-2015 event, where the name doesn't include the owner.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Pre2015Repository {
+    #[serde(default = "id_not_specified")]
+    pub id: i64,
+    pub name: String,
+    pub owner: Option<String>,
+}
+
+impl Pre2015Repository {
+    /// With the name as `owner/name` like later events have it, so names from both eras can be
+    /// compared. Left alone if there's no owner.
+    pub fn as_repo(&self) -> Repo {
+        let name = match self.owner {
+            Some(ref owner) if !self.name.contains('/') => format!("{}/{}", owner, self.name),
+            _ => self.name.clone(),
+        };
+        Repo { id: self.id, name }
+    }
+}
+
 /// A github archive event before 2015.
 #[derive(Deserialize, Debug, Clone)]
 pub struct Pre2015Event {
-    pub repository: Option<Repo>,
+    pub repository: Option<Pre2015Repository>,
     pub repo: Option<Repo>,
     #[serde(rename = "type")]
     pub event_type: String,
@@ -329,6 +542,46 @@ pub struct Pre2015Event {
 }
 
 impl Pre2015Event {
+    /// The same event in the 2015 and later shape, so modes don't need to care which era it's from.
+    /// Old events have no event or actor IDs, so both are -1 like the placeholders `Event::new`
+    /// makes, and `is_missing_data` is true for every one of them. They only have the payload
+    /// fields we read for commit counts. None if the timestamp can't be parsed or there's no repo.
+    pub fn to_event(&self) -> Option<Event> {
+        let created_at = DateTime::parse_from_rfc3339(&self.created_at)
+            .ok()?
+            .with_timezone(&Utc);
+        let repo = match (&self.repo, &self.repository) {
+            (Some(repo), _) => repo.clone(),
+            (None, Some(repository)) => repository.as_repo(),
+            (None, None) => return None,
+        };
+        // Merged PRs are credited to the PR's author, which old events only have as the actor.
+        let payload = self.payload.as_ref().map(|payload| Payload {
+            size: payload.size.map(i64::from),
+            pull_request: payload.pull_request.as_ref().map(|pr| PullRequest {
+                merged: pr.merged,
+                actor: Some(Actor {
+                    id: -1,
+                    login: Some(self.actor_name()),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        Some(Event {
+            id: -1,
+            created_at,
+            event_type: self.event_type.clone(),
+            actor: Actor {
+                id: -1,
+                login: Some(self.actor_name()),
+            },
+            repo,
+            payload,
+            org: None,
+        })
+    }
+
     pub fn is_commit_event(&self) -> bool {
         self.is_accepted_pr() || self.is_direct_push_event()
     }